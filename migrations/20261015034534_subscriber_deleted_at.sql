@@ -0,0 +1 @@
+ALTER TABLE subscriber ADD COLUMN deleted_at TIMESTAMPTZ NULL;
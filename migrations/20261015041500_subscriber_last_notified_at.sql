@@ -0,0 +1 @@
+ALTER TABLE subscriber ADD COLUMN last_notified_at TIMESTAMPTZ NULL;
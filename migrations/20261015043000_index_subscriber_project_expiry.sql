@@ -0,0 +1 @@
+CREATE INDEX subscriber_project_expiry ON subscriber (project, expiry);
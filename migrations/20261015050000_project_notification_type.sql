@@ -0,0 +1,8 @@
+CREATE TABLE project_notification_type (
+    id          uuid        PRIMARY KEY DEFAULT gen_random_uuid(),
+    inserted_at timestamptz NOT NULL DEFAULT now(),
+    project     uuid        NOT NULL REFERENCES project (id) ON DELETE CASCADE,
+    type        uuid        NOT NULL,
+
+    UNIQUE (project, type)
+);
@@ -0,0 +1 @@
+CREATE INDEX subscription_watcher_sym_key ON subscription_watcher (sym_key);
@@ -0,0 +1,10 @@
+CREATE TABLE notification_rate_limit (
+    id           uuid        PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at   timestamptz NOT NULL DEFAULT now(),
+    updated_at   timestamptz NOT NULL DEFAULT now(),
+    project      uuid        NOT NULL REFERENCES project (id) ON DELETE CASCADE,
+    window_start timestamptz NOT NULL,
+    count        bigint      NOT NULL,
+
+    UNIQUE (project, window_start)
+);
@@ -0,0 +1,2 @@
+-- Default collation b-tree indexes can't serve `LIKE 'prefix%'`
+CREATE INDEX project_app_domain_prefix ON project (app_domain text_pattern_ops);
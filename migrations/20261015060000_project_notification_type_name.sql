@@ -0,0 +1,3 @@
+ALTER TABLE project_notification_type ADD COLUMN updated_at timestamptz NOT NULL DEFAULT now();
+ALTER TABLE project_notification_type ADD COLUMN name varchar(255) NULL;
+ALTER TABLE project_notification_type ADD COLUMN description text NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE subscriber_preferences (
+    id                uuid        PRIMARY KEY DEFAULT gen_random_uuid(),
+    created_at        timestamptz NOT NULL DEFAULT now(),
+    updated_at        timestamptz NOT NULL DEFAULT now(),
+    subscriber        uuid        NOT NULL REFERENCES subscriber (id) ON DELETE CASCADE,
+    quiet_hours_start time        NULL,
+    quiet_hours_end   time        NULL,
+    max_per_day       integer     NULL,
+
+    UNIQUE (subscriber),
+    CHECK ((quiet_hours_start IS NULL) = (quiet_hours_end IS NULL)),
+    CHECK (max_per_day IS NULL OR max_per_day >= 0)
+);
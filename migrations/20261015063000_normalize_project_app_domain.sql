@@ -0,0 +1,29 @@
+-- Rows written before normalize_app_domain() may differ only by case or a trailing dot. Which of
+-- those projects should keep the domain can't be decided here, so fail until they're resolved.
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(normalized, ', ')
+    INTO duplicates
+    FROM (
+        SELECT lower(rtrim(app_domain, '.')) AS normalized
+        FROM project
+        GROUP BY lower(rtrim(app_domain, '.'))
+        HAVING count(*) > 1
+    ) AS duplicated;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'Projects share an app_domain once normalized: %', duplicates
+            USING HINT = 'Change the app_domain of all but one project per domain, then migrate again';
+    END IF;
+END
+$$;
+
+UPDATE project
+SET app_domain=lower(rtrim(app_domain, '.')),
+    updated_at=now()
+WHERE app_domain<>lower(rtrim(app_domain, '.'));
+
+ALTER TABLE project ADD CONSTRAINT project_app_domain_normalized
+    CHECK (app_domain=lower(rtrim(app_domain, '.')));
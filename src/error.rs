@@ -2,6 +2,7 @@ use {
     crate::{
         auth,
         rate_limit::{InternalRateLimitError, RateLimitExceeded},
+        rpc::DecodeKeyError,
         services::public_http_server::handlers::notification_link::GetGeoError,
     },
     axum::{response::IntoResponse, Json},
@@ -9,6 +10,7 @@ use {
     relay_client::error::ClientError,
     relay_rpc::{
         auth::ed25519_dalek::ed25519,
+        domain::Topic,
         rpc::{PublishError, SubscriptionError, WatchError},
     },
     serde_json::json,
@@ -78,6 +80,9 @@ pub enum NotifyServerError {
     #[error(transparent)]
     EdDalek(#[from] ed25519::Error),
 
+    #[error("Decode key: {0}")]
+    DecodeKey(#[from] DecodeKeyError),
+
     #[error("sqlx error: {0}")]
     Sqlx(#[from] sqlx::error::Error),
 
@@ -93,6 +98,12 @@ pub enum NotifyServerError {
     #[error("App domain in-use by another project")]
     AppDomainInUseByAnotherProject,
 
+    #[error("Project topic collision: {0}")]
+    ProjectTopicCollision(Topic),
+
+    #[error("Subscriber topic collision: {0}")]
+    SubscriberTopicCollision(Topic),
+
     #[error("Redis pool error: {0}")]
     RedisPool(#[from] deadpool_redis::PoolError),
 
@@ -47,6 +47,7 @@ pub struct Metrics {
     relay_batch_subscribe_request_latency: Histogram<u64>,
     postgres_queries: Counter<u64>,
     postgres_query_latency: Histogram<u64>,
+    postgres_query_errors: Counter<u64>,
     keys_server_requests: Counter<u64>,
     keys_server_request_latency: Histogram<u64>,
     registry_requests: Counter<u64>,
@@ -235,6 +236,11 @@ impl Metrics {
             .with_description("The latency Postgres queries")
             .init();
 
+        let postgres_query_errors: Counter<u64> = meter
+            .u64_counter("postgres_query_errors")
+            .with_description("The number of Postgres queries that returned an error")
+            .init();
+
         let keys_server_requests: Counter<u64> = meter
             .u64_counter("keys_server_requests")
             .with_description("The number of Keys Server requests")
@@ -317,6 +323,7 @@ impl Metrics {
             relay_batch_subscribe_request_latency,
             postgres_queries,
             postgres_query_latency,
+            postgres_query_errors,
             keys_server_requests,
             keys_server_request_latency,
             registry_requests,
@@ -440,6 +447,9 @@ impl Metrics {
             .record(elapsed.as_millis() as u64, &[]);
     }
 
+    /// Called by the `model::helpers` queries with the query name, including
+    /// `get_subscriber_by_topic` and `upsert_subscriber`. Exported on the Prometheus `/metrics`
+    /// endpoint; helpers take `Option<&Metrics>` so passing `None` records nothing.
     pub fn postgres_query(&self, query_name: &'static str, start: Instant) {
         let elapsed = start.elapsed();
 
@@ -449,6 +459,20 @@ impl Metrics {
             .record(elapsed.as_millis() as u64, &attributes);
     }
 
+    /// Like `postgres_query()` but also counts the query as failed if `result` is an error
+    pub fn postgres_query_result<T, E>(
+        &self,
+        query_name: &'static str,
+        start: Instant,
+        result: &Result<T, E>,
+    ) {
+        self.postgres_query(query_name, start);
+        if result.is_err() {
+            self.postgres_query_errors
+                .add(1, &[KeyValue::new("name", query_name)]);
+        }
+    }
+
     pub fn keys_server_request(&self, start: Instant, source: &KeysServerResponseSource) {
         let elapsed = start.elapsed();
 
@@ -1,36 +1,194 @@
 use {
-    super::types::{Project, Subscriber},
+    super::types::{Project, ProjectKeys, ProjectPublic, Subscriber},
     crate::{
         auth::{
             encode_authentication_private_key, encode_authentication_public_key,
             encode_subscribe_private_key, encode_subscribe_public_key,
         },
         metrics::Metrics,
-        model::types::AccountId,
-        utils::get_address_from_account,
+        model::types::{
+            caip10::{validate_caip_10, Caip10Error},
+            AccountId, SymKey,
+        },
+        rpc::DecodeKeyError,
+        utils::{get_address_from_account, topic_from_key},
     },
-    chrono::{DateTime, Utc},
+    chrono::{DateTime, NaiveTime, Utc},
+    futures::{Stream, StreamExt, TryStreamExt},
+    once_cell::sync::Lazy,
     relay_rpc::{
         auth::ed25519_dalek::SigningKey,
         domain::{ProjectId, Topic},
     },
     serde::{Deserialize, Serialize},
     sqlx::{FromRow, PgPool, Postgres},
-    std::{collections::HashSet, time::Instant},
-    tracing::{error, instrument},
+    std::{
+        collections::{HashMap, HashSet},
+        future::Future,
+        time::{Duration, Instant},
+    },
+    tracing::{error, instrument, warn, Instrument},
     uuid::Uuid,
     validator::{Validate, ValidationError},
-    x25519_dalek::StaticSecret,
+    x25519_dalek::{PublicKey, StaticSecret},
 };
 
 // Import not part of group above because it breaks formatting: https://github.com/rust-lang/rustfmt/issues/4746
 use crate::services::public_http_server::handlers::relay_webhook::handlers::notify_watch_subscriptions::SUBSCRIPTION_WATCHER_LIMIT;
 
+const READ_RETRIES: u32 = 2;
+const READ_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// Errors that may succeed if the query is simply tried again
+fn is_transient_error(e: &sqlx::error::Error) -> bool {
+    matches!(e, sqlx::Error::PoolTimedOut | sqlx::Error::Io(_))
+}
+
+/// Runs `f`, retrying up to `retries` times with exponential backoff starting at `base_delay` if it
+/// fails with a transient error. Other errors (e.g. `RowNotFound`) are returned immediately.
+async fn with_retry<T, F, Fut>(
+    retries: u32,
+    base_delay: Duration,
+    mut f: F,
+) -> Result<T, sqlx::error::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::error::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Err(e) if attempt < retries && is_transient_error(&e) => {
+                let retry_in = base_delay * 2u32.pow(attempt);
+                attempt += 1;
+                warn!(
+                    "Transient database error, retrying attempt {attempt} in {retry_in:?}: {e:?}"
+                );
+                tokio::time::sleep(retry_in).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// https://www.postgresql.org/docs/current/errcodes-appendix.html
+const QUERY_CANCELED_ERROR_CODE: &str = "57014";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScanError {
+    #[error("Statement timed out")]
+    StatementTimeout,
+
+    #[error("Statement timeout of zero would disable the timeout")]
+    ZeroStatementTimeout,
+
+    #[error(transparent)]
+    Sqlx(sqlx::error::Error),
+}
+
+impl From<sqlx::error::Error> for ScanError {
+    fn from(e: sqlx::error::Error) -> Self {
+        match e {
+            sqlx::Error::Database(e) if e.code().as_deref() == Some(QUERY_CANCELED_ERROR_CODE) => {
+                ScanError::StatementTimeout
+            }
+            e => ScanError::Sqlx(e),
+        }
+    }
+}
+
+/// Begins a transaction in which statements are canceled after `statement_timeout`, so that long
+/// scans against a degraded database don't pile up. Canceled statements convert into
+/// `ScanError::StatementTimeout`.
+///
+/// Postgres takes the timeout in whole milliseconds and treats 0 as no timeout, so a zero
+/// `statement_timeout` is rejected and anything shorter than 1ms is rounded up.
+pub async fn begin_with_statement_timeout(
+    statement_timeout: Duration,
+    postgres: &PgPool,
+) -> Result<sqlx::Transaction<'static, Postgres>, ScanError> {
+    if statement_timeout.is_zero() {
+        return Err(ScanError::ZeroStatementTimeout);
+    }
+    let statement_timeout_ms = (statement_timeout.as_nanos() + 999_999) / 1_000_000;
+
+    let mut txn = postgres.begin().await?;
+    // SET doesn't accept bind parameters
+    sqlx::query::<Postgres>(&format!(
+        "SET LOCAL statement_timeout = {statement_timeout_ms}"
+    ))
+    .execute(&mut *txn)
+    .await?;
+    Ok(txn)
+}
+
 #[derive(Debug, FromRow)]
 pub struct ProjectWithPublicKeys {
+    pub id: Uuid,
     pub authentication_public_key: String,
     pub subscribe_public_key: String,
     pub topic: String,
+    #[sqlx(rename = "inserted_at")]
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct UpsertOutcome {
+    #[sqlx(flatten)]
+    pub keys: ProjectWithPublicKeys,
+    /// `false` if the project already existed and was updated
+    pub inserted: bool,
+}
+
+// Domains max at 253 chars according to: https://en.wikipedia.org/wiki/Hostname
+const APP_DOMAIN_MAX_LENGTH: usize = 253;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppDomainError {
+    #[error("App domain exceeds {APP_DOMAIN_MAX_LENGTH} characters")]
+    TooLong,
+
+    #[error("App domain is not a valid domain")]
+    Invalid,
+}
+
+/// Canonical form of an app domain: lowercase and without the trailing dot of a FQDN. A leading
+/// `www.` is kept since it can be a different app than the bare domain.
+pub fn normalize_app_domain(app_domain: &str) -> Result<String, AppDomainError> {
+    let app_domain = app_domain.to_ascii_lowercase();
+    let app_domain = app_domain.strip_suffix('.').unwrap_or(&app_domain);
+    if app_domain.len() > APP_DOMAIN_MAX_LENGTH {
+        return Err(AppDomainError::TooLong);
+    }
+    let valid = app_domain.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    });
+    if !valid {
+        return Err(AppDomainError::Invalid);
+    }
+    Ok(app_domain.to_owned())
+}
+
+/// For lookups an invalid domain can't match any stored project, so it's used as-is instead of
+/// being an error
+fn normalize_app_domain_for_lookup(app_domain: &str) -> String {
+    normalize_app_domain(app_domain).unwrap_or_else(|_| app_domain.to_owned())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpsertProjectError {
+    #[error("Invalid app domain: {0}")]
+    InvalidAppDomain(#[from] AppDomainError),
+
+    #[error("Project topic {0} already in-use by another project")]
+    TopicCollision(Topic),
+
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::error::Error),
 }
 
 pub async fn upsert_project(
@@ -41,7 +199,7 @@ pub async fn upsert_project(
     subscribe_key: &StaticSecret,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<ProjectWithPublicKeys, sqlx::error::Error> {
+) -> Result<UpsertOutcome, UpsertProjectError> {
     let authentication_public_key = encode_authentication_public_key(authentication_key);
     let authentication_private_key = encode_authentication_private_key(authentication_key);
     let subscribe_public_key = encode_subscribe_public_key(subscribe_key);
@@ -60,10 +218,35 @@ pub async fn upsert_project(
     .await
 }
 
-// TODO test idempotency
+/// Same as `upsert_project()` but runs inside the caller's transaction so it can be composed with
+/// other writes
+#[instrument(skip(authentication_key, subscribe_key, txn, metrics))]
+pub async fn upsert_project_tx(
+    project_id: ProjectId,
+    app_domain: &str,
+    topic: Topic,
+    authentication_key: &SigningKey,
+    subscribe_key: &StaticSecret,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<UpsertOutcome, UpsertProjectError> {
+    upsert_project_impl(
+        project_id,
+        app_domain,
+        topic,
+        encode_authentication_public_key(authentication_key),
+        encode_authentication_private_key(authentication_key),
+        encode_subscribe_public_key(subscribe_key),
+        encode_subscribe_private_key(subscribe_key),
+        &mut **txn,
+        metrics,
+    )
+    .await
+}
+
 #[allow(clippy::too_many_arguments)]
 #[instrument(skip(authentication_private_key, subscribe_private_key, postgres, metrics))]
-async fn upsert_project_impl(
+async fn upsert_project_impl<'e>(
     project_id: ProjectId,
     app_domain: &str,
     topic: Topic,
@@ -71,9 +254,12 @@ async fn upsert_project_impl(
     authentication_private_key: String,
     subscribe_public_key: String,
     subscribe_private_key: String,
-    postgres: &PgPool,
+    postgres: impl sqlx::PgExecutor<'e>,
     metrics: Option<&Metrics>,
-) -> Result<ProjectWithPublicKeys, sqlx::error::Error> {
+) -> Result<UpsertOutcome, UpsertProjectError> {
+    let app_domain = normalize_app_domain(app_domain)?;
+
+    // `xmax = 0`: https://stackoverflow.com/a/39204667
     let query = "
         INSERT INTO project (
             project_id,
@@ -88,776 +274,3955 @@ async fn upsert_project_impl(
         ON CONFLICT (project_id) DO UPDATE SET
             updated_at=now(),
             app_domain=$2
-        RETURNING authentication_public_key, subscribe_public_key, topic
+        RETURNING
+            id,
+            authentication_public_key,
+            subscribe_public_key,
+            topic,
+            inserted_at,
+            updated_at,
+            (xmax = 0) AS inserted
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, ProjectWithPublicKeys>(query)
+    let result = sqlx::query_as::<Postgres, UpsertOutcome>(query)
         .bind(project_id.as_ref())
-        .bind(app_domain)
+        .bind(&app_domain)
         .bind(topic.as_ref())
         .bind(authentication_public_key)
         .bind(authentication_private_key)
         .bind(subscribe_public_key)
         .bind(subscribe_private_key)
         .fetch_one(postgres)
-        .await;
+        .await
+        .map_err(|e| match e {
+            // Topic is derived from the subscribe key, so a collision here is a key generation
+            // problem rather than something retrying would fix
+            sqlx::Error::Database(e)
+                if e.is_unique_violation() && e.message().contains("project_topic_key") =>
+            {
+                UpsertProjectError::TopicCollision(topic)
+            }
+            other => other.into(),
+        });
     if let Some(metrics) = metrics {
-        metrics.postgres_query("upsert_project_impl", start);
+        metrics.postgres_query_result("upsert_project_impl", start, &result);
     }
     result
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum UpsertProjectAndSubscriberError {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+
+    #[error("Invalid app domain: {0}")]
+    InvalidAppDomain(#[from] AppDomainError),
+
+    #[error("Invalid account: {0}")]
+    InvalidAccount(#[from] Caip10Error),
+
+    #[error("Project topic {0} already in-use by another project")]
+    TopicCollision(Topic),
+
+    #[error("Subscriber topic {0} already in-use by another subscriber")]
+    SubscriberTopicCollision(Topic),
+
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+impl From<UpsertProjectError> for UpsertProjectAndSubscriberError {
+    fn from(err: UpsertProjectError) -> Self {
+        match err {
+            UpsertProjectError::InvalidAppDomain(e) => Self::InvalidAppDomain(e),
+            UpsertProjectError::TopicCollision(topic) => Self::TopicCollision(topic),
+            UpsertProjectError::Sqlx(e) => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<UpsertSubscriberError> for UpsertProjectAndSubscriberError {
+    fn from(err: UpsertSubscriberError) -> Self {
+        match err {
+            UpsertSubscriberError::Scope(e) => Self::Scope(e),
+            UpsertSubscriberError::InvalidAccount(e) => Self::InvalidAccount(e),
+            UpsertSubscriberError::TopicCollision(topic) => Self::SubscriberTopicCollision(topic),
+            UpsertSubscriberError::Sqlx(e) => Self::Sqlx(e),
+        }
+    }
+}
+
+/// Upserts the project and subscribes the account to it in one transaction. Returns the project's
+/// public keys and the subscriber ID.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(authentication_key, subscribe_key, postgres, metrics))]
+pub async fn upsert_project_and_subscriber(
+    project_id: ProjectId,
+    app_domain: &str,
+    project_topic: Topic,
+    authentication_key: &SigningKey,
+    subscribe_key: &StaticSecret,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(ProjectWithPublicKeys, Uuid), UpsertProjectAndSubscriberError> {
+    validate_scope(&scope, scope_policy)?;
+    validate_account(&account)?;
+
+    let mut txn = postgres.begin().await?;
+    let project = upsert_project_tx(
+        project_id,
+        app_domain,
+        project_topic,
+        authentication_key,
+        subscribe_key,
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    let subscriber = upsert_subscriber_impl(
+        project.keys.id,
+        account,
+        scope,
+        scope_policy,
+        notify_key,
+        notify_topic,
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    txn.commit().await?;
+
+    Ok((project.keys, subscriber.subscriber.id))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpdateProjectAppDomainError {
+    #[error("Invalid app domain: {0}")]
+    InvalidAppDomain(#[from] AppDomainError),
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+/// Moves the project to a new app domain. The topic is derived from the subscribe key, not the app
+/// domain, so it stays the same and existing relay subscriptions remain valid.
 #[instrument(skip(postgres, metrics))]
-pub async fn get_project_by_id(
-    id: Uuid,
+pub async fn update_project_app_domain(
+    project_id: ProjectId,
+    new_app_domain: &str,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Project, sqlx::error::Error> {
+) -> Result<Project, UpdateProjectAppDomainError> {
+    let new_app_domain = normalize_app_domain(new_app_domain)?;
+
     let query = "
-        SELECT *
-        FROM project
-        WHERE id=$1
+        UPDATE project
+        SET updated_at=now(),
+            app_domain=$2
+        WHERE project_id=$1
+        RETURNING *
     ";
     let start = Instant::now();
     let result = sqlx::query_as::<Postgres, Project>(query)
-        .bind(id)
+        .bind(project_id.as_ref())
+        .bind(new_app_domain)
         .fetch_one(postgres)
         .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_project_by_id", start);
+        metrics.postgres_query_result("update_project_app_domain", start, &result);
     }
-    result
+    Ok(result?)
 }
 
-#[instrument(skip(postgres, metrics))]
-pub async fn get_project_by_project_id(
+/// Replaces the project's subscribe key, returning the new public key. The project topic is derived
+/// from the subscribe public key, so it is updated too and the caller must subscribe to the new
+/// topic. Fails with `RowNotFound` if the project doesn't exist.
+#[instrument(skip(new_key, postgres, metrics))]
+pub async fn rotate_subscribe_key(
     project_id: ProjectId,
+    new_key: &StaticSecret,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Project, sqlx::error::Error> {
+) -> Result<String, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct RotateResult {
+        subscribe_public_key: String,
+    }
     let query = "
-        SELECT *
-        FROM project
+        UPDATE project
+        SET updated_at=now(),
+            topic=$2,
+            subscribe_public_key=$3,
+            subscribe_private_key=$4
         WHERE project_id=$1
+        RETURNING subscribe_public_key
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, Project>(query)
+    let result = sqlx::query_as::<Postgres, RotateResult>(query)
         .bind(project_id.as_ref())
+        .bind(topic_from_key(PublicKey::from(new_key).as_bytes()).as_ref())
+        .bind(encode_subscribe_public_key(new_key))
+        .bind(encode_subscribe_private_key(new_key))
         .fetch_one(postgres)
         .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_project_by_project_id", start);
+        metrics.postgres_query_result("rotate_subscribe_key", start, &result);
     }
-    result
+    Ok(result?.subscribe_public_key)
 }
 
-#[instrument(skip(postgres, metrics))]
-pub async fn get_project_by_app_domain(
-    app_domain: &str,
+/// Replaces the project's authentication key, returning the new public key. Fails with
+/// `RowNotFound` if the project doesn't exist.
+#[instrument(skip(new_key, postgres, metrics))]
+pub async fn rotate_authentication_key(
+    project_id: ProjectId,
+    new_key: &SigningKey,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Project, sqlx::error::Error> {
+) -> Result<String, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct RotateResult {
+        authentication_public_key: String,
+    }
     let query = "
-        SELECT *
-        FROM project
-        WHERE app_domain=$1
+        UPDATE project
+        SET updated_at=now(),
+            authentication_public_key=$2,
+            authentication_private_key=$3
+        WHERE project_id=$1
+        RETURNING authentication_public_key
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, Project>(query)
-        .bind(app_domain)
+    let result = sqlx::query_as::<Postgres, RotateResult>(query)
+        .bind(project_id.as_ref())
+        .bind(encode_authentication_public_key(new_key))
+        .bind(encode_authentication_private_key(new_key))
         .fetch_one(postgres)
         .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_project_by_app_domain", start);
+        metrics.postgres_query_result("rotate_authentication_key", start, &result);
     }
-    result
+    Ok(result?.authentication_public_key)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteProjectError {
+    #[error("Project not found")]
+    ProjectNotFound,
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
 }
 
+/// Deletes the project along with everything that belongs to it. Subscribers (and their scopes and
+/// notifications), subscription watchers, notifications, welcome notification, notification types
+/// and send counts are removed by their foreign keys' `ON DELETE CASCADE`, in the same statement.
 #[instrument(skip(postgres, metrics))]
-pub async fn get_project_by_topic(
-    topic: Topic,
+pub async fn delete_project(
+    project_id: ProjectId,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Project, sqlx::error::Error> {
+) -> Result<(), DeleteProjectError> {
     let query = "
-        SELECT *
-        FROM project
-        WHERE topic=$1
+        DELETE FROM project
+        WHERE project_id=$1
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, Project>(query)
-        .bind(topic.as_ref())
-        .fetch_one(postgres)
+    let result = sqlx::query::<Postgres>(query)
+        .bind(project_id.as_ref())
+        .execute(postgres)
         .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_project_by_topic", start);
+        metrics.postgres_query_result("delete_project", start, &result);
     }
-    result
+    let result = result?;
+    if result.rows_affected() == 0 {
+        return Err(DeleteProjectError::ProjectNotFound);
+    }
+    Ok(())
 }
 
-// FIXME scaling: response not paginated
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscriber_accounts_by_project_id(
-    project_id: ProjectId,
+pub async fn get_project_by_id(
+    id: Uuid,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<AccountId>, sqlx::error::Error> {
-    #[derive(Debug, FromRow)]
-    struct SubscriberAccount {
-        #[sqlx(try_from = "String")]
-        account: AccountId,
-    }
+) -> Result<Project, sqlx::error::Error> {
     let query = "
-        SELECT account
-        FROM subscriber
-        JOIN project ON project.id=subscriber.project
-        WHERE project.project_id=$1
+        SELECT *
+        FROM project
+        WHERE id=$1
     ";
     let start = Instant::now();
-    let subscribers = sqlx::query_as::<Postgres, SubscriberAccount>(query)
-        .bind(project_id.as_ref())
-        .fetch_all(postgres)
-        .await?;
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, Project>(query)
+            .bind(id)
+            .fetch_one(postgres)
+    })
+    .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscriber_accounts_by_project_id", start);
+        metrics.postgres_query_result("get_project_by_id", start, &result);
     }
-    Ok(subscribers.into_iter().map(|p| p.account).collect())
+    result
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
-pub struct SubscriberAccountAndScopes {
-    pub account: AccountId,
-    pub scope: HashSet<Uuid>,
+#[derive(Debug, thiserror::Error)]
+pub enum GetProjectKeysError {
+    #[error("Project keys are corrupt: {0}")]
+    DecodeKey(#[from] DecodeKeyError),
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
 }
 
+/// Same as `get_project_by_id()` but returns the project's private keys already decoded. Returns
+/// `Sqlx(RowNotFound)` if there is no such project and `DecodeKey` if the stored keys are invalid.
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscribers_by_project_id_and_accounts(
+pub async fn get_project_keys_by_id(
+    id: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<ProjectKeys, GetProjectKeysError> {
+    let project = get_project_by_id(id, postgres, metrics).await?;
+    Ok(project.keys()?)
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_by_project_id(
     project_id: ProjectId,
-    accounts: &[AccountId],
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<SubscriberAccountAndScopes>, sqlx::error::Error> {
-    #[derive(Debug, FromRow)]
-    struct ResultSubscriberAccountAndScopes {
-        #[sqlx(try_from = "String")]
-        account: AccountId,
-        scope: Vec<String>,
-    }
+) -> Result<Project, sqlx::error::Error> {
     let query = "
-        SELECT account, array_remove(array_agg(subscriber_scope.name), NULL) AS scope
-        FROM subscriber
-        JOIN project ON project.id=subscriber.project
-        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
-        WHERE project.project_id=$1
-              AND get_address_lower(account)=ANY($2)
-        GROUP BY account
+        SELECT *
+        FROM project
+        WHERE project_id=$1
     ";
     let start = Instant::now();
-    let projects = sqlx::query_as::<Postgres, ResultSubscriberAccountAndScopes>(query)
-        .bind(project_id.as_ref())
-        .bind(
-            accounts
-                .iter()
-                .map(|account| get_address_from_account(account).to_ascii_lowercase())
-                .collect::<Vec<_>>(),
-        )
-        .fetch_all(postgres)
-        .await?;
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, Project>(query)
+            .bind(project_id.as_ref())
+            .fetch_one(postgres)
+    })
+    .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscribers_by_project_id_and_accounts", start);
+        metrics.postgres_query_result("get_project_by_project_id", start, &result);
     }
-    Ok(projects
-        .into_iter()
-        .map(|s| SubscriberAccountAndScopes {
-            account: s.account,
-            scope: parse_scopes_and_ignore_invalid(&s.scope),
-        })
-        .collect())
+    result
 }
 
-// FIXME scaling: response not paginated
+/// Backed by the index of the `UNIQUE` constraint on `project.authentication_public_key` from the
+/// init migration; keep an index on this column if that constraint is ever changed.
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscriber_topics(
+pub async fn get_project_by_authentication_public_key(
+    authentication_public_key: &str,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<Topic>, sqlx::error::Error> {
-    #[derive(Debug, FromRow)]
-    struct SubscriberWithTopic {
-        #[sqlx(try_from = "String")]
-        topic: Topic,
-    }
+) -> Result<Option<Project>, sqlx::error::Error> {
     let query = "
-        SELECT topic
-        FROM subscriber
+        SELECT *
+        FROM project
+        WHERE authentication_public_key=$1
     ";
     let start = Instant::now();
-    let subscribers = sqlx::query_as::<Postgres, SubscriberWithTopic>(query)
-        .fetch_all(postgres)
-        .await?;
+    let result = sqlx::query_as::<Postgres, Project>(query)
+        .bind(authentication_public_key)
+        .fetch_optional(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscriber_topics", start);
+        metrics.postgres_query_result("get_project_by_authentication_public_key", start, &result);
     }
-    Ok(subscribers.into_iter().map(|p| p.topic).collect())
+    result
 }
 
-// FIXME scaling: response not paginated
+const PROJECT_PUBLIC_COLUMNS: &str = "
+    id,
+    project_id,
+    app_domain,
+    topic,
+    authentication_public_key,
+    subscribe_public_key,
+    inserted_at,
+    updated_at
+";
+
 #[instrument(skip(postgres, metrics))]
-pub async fn get_project_topics(
+pub async fn get_project_public_by_id(
+    id: Uuid,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<Topic>, sqlx::error::Error> {
-    #[derive(Debug, FromRow)]
-    struct ProjectWithTopic {
-        #[sqlx(try_from = "String")]
-        topic: Topic,
-    }
-    let query = "
-        SELECT topic
+) -> Result<ProjectPublic, sqlx::error::Error> {
+    let query = format!(
+        "
+        SELECT {PROJECT_PUBLIC_COLUMNS}
         FROM project
-    ";
+        WHERE id=$1
+        "
+    );
     let start = Instant::now();
-    let projects = sqlx::query_as::<Postgres, ProjectWithTopic>(query)
-        .fetch_all(postgres)
-        .await?;
+    let result = sqlx::query_as::<Postgres, ProjectPublic>(&query)
+        .bind(id)
+        .fetch_one(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_project_topics", start);
+        metrics.postgres_query_result("get_project_public_by_id", start, &result);
     }
-    Ok(projects.into_iter().map(|p| p.topic).collect())
-}
-
-#[derive(Debug, FromRow)]
-pub struct SubscribeResponse {
-    pub id: Uuid,
-    #[sqlx(try_from = "String")]
-    pub account: AccountId,
-    #[sqlx(try_from = "String")]
-    pub topic: Topic,
-    pub inserted: bool,
+    result
 }
 
-// TODO test idempotency
 #[instrument(skip(postgres, metrics))]
-pub async fn upsert_subscriber(
-    project: Uuid,
-    account: AccountId,
-    scope: HashSet<Uuid>,
-    notify_key: &[u8; 32],
-    notify_topic: Topic,
+pub async fn get_project_public_by_project_id(
+    project_id: ProjectId,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<SubscribeResponse, sqlx::error::Error> {
-    let mut txn = postgres.begin().await?;
-
-    // `xmax = 0`: https://stackoverflow.com/a/39204667
-
-    let query = "
-        INSERT INTO subscriber (
-            project,
-            account,
-            sym_key,
-            topic,
-            expiry
-        )
-        VALUES ($1, $2, $3, $4, $5)
-        ON CONFLICT (project, get_address_lower(account)) DO UPDATE SET
-            updated_at=now(),
-            expiry=$5
-        RETURNING
-            id,
-            account,
-            topic,
-            (xmax = 0) AS inserted
-    ";
+) -> Result<ProjectPublic, sqlx::error::Error> {
+    let query = format!(
+        "
+        SELECT {PROJECT_PUBLIC_COLUMNS}
+        FROM project
+        WHERE project_id=$1
+        "
+    );
     let start = Instant::now();
-    let subscriber = sqlx::query_as::<Postgres, SubscribeResponse>(query)
-        .bind(project)
-        .bind(account.as_ref())
-        .bind(hex::encode(notify_key))
-        .bind(notify_topic.as_ref())
-        .bind(Utc::now() + chrono::Duration::days(30))
-        .fetch_one(&mut *txn)
-        .await?;
+    let result = sqlx::query_as::<Postgres, ProjectPublic>(&query)
+        .bind(project_id.as_ref())
+        .fetch_one(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("upsert_subscriber", start);
+        metrics.postgres_query_result("get_project_public_by_project_id", start, &result);
     }
-
-    update_subscriber_scope(subscriber.id, scope, &mut txn, metrics).await?;
-
-    txn.commit().await?;
-
-    Ok(subscriber)
+    result
 }
 
-// TODO test idempotency
+/// Projects are returned in no particular order and project IDs that don't exist are skipped, so
+/// callers should match the results on `project_id`.
 #[instrument(skip(postgres, metrics))]
-pub async fn update_subscriber(
-    subscriber: Uuid,
-    scope: HashSet<Uuid>,
+pub async fn get_projects_by_project_ids(
+    project_ids: &[ProjectId],
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Subscriber, sqlx::error::Error> {
-    let mut txn = postgres.begin().await?;
-
+) -> Result<Vec<Project>, sqlx::error::Error> {
     let query = "
-        UPDATE subscriber
-        SET updated_at=now(),
-            expiry=$1
-        WHERE id=$2
-        RETURNING *
+        SELECT *
+        FROM project
+        WHERE project_id=ANY($1)
     ";
     let start = Instant::now();
-    let updated_subscriber = sqlx::query_as::<_, Subscriber>(query)
-        .bind(Utc::now() + chrono::Duration::days(30))
-        .bind(subscriber)
-        .fetch_one(&mut *txn)
-        .await?;
-    assert_eq!(updated_subscriber.id, subscriber);
+    let result = sqlx::query_as::<Postgres, Project>(query)
+        .bind(
+            project_ids
+                .iter()
+                .map(|project_id| project_id.as_ref())
+                .collect::<Vec<&str>>(),
+        )
+        .fetch_all(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("update_subscriber", start);
+        metrics.postgres_query_result("get_projects_by_project_ids", start, &result);
     }
-
-    update_subscriber_scope(updated_subscriber.id, scope, &mut txn, metrics).await?;
-
-    txn.commit().await?;
-
-    Ok(updated_subscriber)
+    result
 }
 
-// TODO limit to 15 scopes
-async fn update_subscriber_scope(
-    subscriber: Uuid,
-    scope: HashSet<Uuid>,
-    txn: &mut sqlx::Transaction<'_, Postgres>,
+/// Projects are returned in no particular order and IDs that don't exist are skipped, so callers
+/// should match the results on `id`
+#[instrument(skip(postgres, metrics))]
+pub async fn get_projects_by_ids(
+    ids: &[Uuid],
+    postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<(), sqlx::error::Error> {
-    let query = "
-        DELETE FROM subscriber_scope
-        WHERE subscriber=$1
-    ";
-    let start = Instant::now();
-    sqlx::query(query)
-        .bind(subscriber)
-        .execute(&mut **txn)
-        .await?;
-    if let Some(metrics) = metrics {
-        metrics.postgres_query("update_subscriber_scope.delete", start);
+) -> Result<Vec<Project>, sqlx::error::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
     }
 
     let query = "
-        INSERT INTO subscriber_scope ( subscriber, name )
-        SELECT $1 AS subscriber, name FROM UNNEST($2) AS name;
+        SELECT *
+        FROM project
+        WHERE id=ANY($1)
     ";
     let start = Instant::now();
-    let _ = sqlx::query::<Postgres>(query)
-        .bind(subscriber)
-        .bind(scope.into_iter().collect::<Vec<_>>())
-        .execute(&mut **txn)
-        .await?;
+    let result = sqlx::query_as::<Postgres, Project>(query)
+        .bind(ids)
+        .fetch_all(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("update_subscriber_scope.insert", start);
+        metrics.postgres_query_result("get_projects_by_ids", start, &result);
     }
-
-    Ok(())
+    result
 }
 
 #[instrument(skip(postgres, metrics))]
-pub async fn delete_subscriber(
-    subscriber: Uuid,
+pub async fn get_project_by_app_domain(
+    app_domain: &str,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<(), sqlx::error::Error> {
+) -> Result<Project, sqlx::error::Error> {
+    let app_domain = normalize_app_domain_for_lookup(app_domain);
     let query = "
-        DELETE FROM subscriber
-        WHERE id=$1
+        SELECT *
+        FROM project
+        WHERE app_domain=$1
     ";
     let start = Instant::now();
-    let _ = sqlx::query::<Postgres>(query)
-        .bind(subscriber)
-        .execute(postgres)
-        .await?;
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, Project>(query)
+            .bind(&app_domain)
+            .fetch_one(postgres)
+    })
+    .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("delete_subscriber", start);
+        metrics.postgres_query_result("get_project_by_app_domain", start, &result);
     }
-    Ok(())
-}
-
-pub struct SubscriberWithScope {
-    pub id: Uuid,
-    pub project: Uuid,
-    pub account: AccountId,
-    pub sym_key: String,
-    pub topic: Topic,
-    pub scope: HashSet<Uuid>,
-    pub expiry: DateTime<Utc>,
-}
-
-#[derive(FromRow)]
-pub struct SubscriberWithScopeResult {
-    pub id: Uuid,
-    pub project: Uuid,
-    #[sqlx(try_from = "String")]
-    pub account: AccountId,
-    pub sym_key: String,
-    #[sqlx(try_from = "String")]
-    pub topic: Topic,
-    pub scope: Vec<String>,
-    pub expiry: DateTime<Utc>,
+    result
 }
 
-impl From<SubscriberWithScopeResult> for SubscriberWithScope {
-    fn from(val: SubscriberWithScopeResult) -> Self {
-        SubscriberWithScope {
-            id: val.id,
-            project: val.project,
-            account: val.account,
-            sym_key: val.sym_key,
-            topic: val.topic,
-            scope: parse_scopes_and_ignore_invalid(&val.scope),
-            expiry: val.expiry,
-        }
-    }
+/// Returns `RowNotFound` if there is no project with this topic. Use `try_get_project_by_topic()`
+/// when an unknown topic is expected, e.g. for messages arriving on a shared relay.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_by_topic(
+    topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Project, sqlx::error::Error> {
+    try_get_project_by_topic(topic, postgres, metrics)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)
 }
 
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscriber_by_topic(
+pub async fn try_get_project_by_topic(
     topic: Topic,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<SubscriberWithScope, sqlx::error::Error> {
+) -> Result<Option<Project>, sqlx::error::Error> {
     let query = "
-        SELECT subscriber.id, project, account, sym_key, array_remove(array_agg(subscriber_scope.name), NULL) AS \
-                 scope, topic, expiry
-        FROM subscriber
-        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        SELECT *
+        FROM project
         WHERE topic=$1
-        GROUP BY subscriber.id, project, account, sym_key, topic, expiry
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(query)
-        .bind(topic.as_ref())
-        .fetch_one(postgres)
-        .await
-        .map(Into::into);
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, Project>(query)
+            .bind(topic.as_ref())
+            .fetch_optional(postgres)
+    })
+    .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscriber_by_topic", start);
+        metrics.postgres_query_result("get_project_by_topic", start, &result);
     }
     result
 }
 
-pub struct NotifySubscriberInfo {
-    pub id: Uuid,
-    pub account: AccountId,
-    pub scope: HashSet<Uuid>,
-}
-
-#[derive(FromRow)]
-pub struct NotifySubscriberInfoResult {
-    pub id: Uuid,
-    #[sqlx(try_from = "String")]
-    pub account: AccountId,
-    pub scope: Vec<String>,
-}
-
-impl From<NotifySubscriberInfoResult> for NotifySubscriberInfo {
-    fn from(val: NotifySubscriberInfoResult) -> Self {
-        NotifySubscriberInfo {
-            id: val.id,
-            account: val.account,
-            scope: parse_scopes_and_ignore_invalid(&val.scope),
-        }
-    }
-}
+pub const LIST_PROJECTS_LIMIT_MAX: i64 = 1000;
 
+/// Keyset pagination ordered by `id`. Pass the `id` of the last project of the previous page as
+/// `after` to get the next page. An empty response means there are no more projects.
+///
+/// Returned projects include their private keys; strip them before exposing the response.
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscribers_for_project_in(
-    project: Uuid,
-    accounts: &[AccountId],
+pub async fn list_projects(
+    after: Option<Uuid>,
+    limit: i64,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<NotifySubscriberInfo>, sqlx::error::Error> {
-    let query = "
-        SELECT subscriber.id, account, array_remove(array_agg(subscriber_scope.name), NULL) AS scope
+) -> Result<Vec<Project>, sqlx::error::Error> {
+    let after_clause = if after.is_some() { "WHERE id > $2" } else { "" };
+    let query = &format!(
+        "
+        SELECT *
+        FROM project
+        {after_clause}
+        ORDER BY id
+        LIMIT $1
+        "
+    );
+    let mut builder =
+        sqlx::query_as::<Postgres, Project>(query).bind(limit.clamp(0, LIST_PROJECTS_LIMIT_MAX));
+    builder = if let Some(after) = after {
+        builder.bind(after)
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder.fetch_all(postgres).await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("list_projects", start, &result);
+    }
+    result
+}
+
+pub const SEARCH_PROJECTS_LIMIT_MAX: i64 = 100;
+
+/// Escapes `LIKE` wildcards so that they match literally
+fn escape_like_pattern(pattern: &str) -> String {
+    pattern
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Projects whose app domain starts with `prefix`, ordered by app domain. `%` and `_` in `prefix`
+/// are matched literally.
+///
+/// Returned projects include their private keys; strip them before exposing the response.
+#[instrument(skip(postgres, metrics))]
+pub async fn search_projects_by_app_domain_prefix(
+    prefix: &str,
+    limit: i64,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Project>, sqlx::error::Error> {
+    let query = "
+        SELECT *
+        FROM project
+        WHERE app_domain LIKE $1 || '%'
+        ORDER BY app_domain
+        LIMIT $2
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, Project>(query)
+        .bind(escape_like_pattern(&prefix.to_ascii_lowercase()))
+        .bind(limit.clamp(0, SEARCH_PROJECTS_LIMIT_MAX))
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("search_projects_by_app_domain_prefix", start, &result);
+    }
+    result
+}
+
+// FIXME scaling: response not paginated
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_accounts_by_project_id(
+    project_id: ProjectId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<AccountId>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct SubscriberAccount {
+        #[sqlx(try_from = "String")]
+        account: AccountId,
+    }
+    let query = "
+        SELECT account
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        WHERE project.project_id=$1
+              AND subscriber.deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberAccount>(query)
+        .bind(project_id.as_ref())
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_accounts_by_project_id", start, &result);
+    }
+    let subscribers = result?;
+    Ok(subscribers.into_iter().map(|p| p.account).collect())
+}
+
+// FIXME scaling: response not paginated
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_accounts_with_expiry(
+    project_id: ProjectId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<(AccountId, DateTime<Utc>)>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct SubscriberAccountWithExpiry {
+        #[sqlx(try_from = "String")]
+        account: AccountId,
+        expiry: DateTime<Utc>,
+    }
+    let query = "
+        SELECT account, expiry
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        WHERE project.project_id=$1
+              AND deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberAccountWithExpiry>(query)
+        .bind(project_id.as_ref())
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_accounts_with_expiry", start, &result);
+    }
+    let subscribers = result?;
+    Ok(subscribers
+        .into_iter()
+        .map(|p| (p.account, p.expiry))
+        .collect())
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SubscriberAccountAndScopes {
+    pub account: AccountId,
+    pub scope: HashSet<Uuid>,
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_by_project_id_and_accounts(
+    project_id: ProjectId,
+    accounts: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberAccountAndScopes>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct ResultSubscriberAccountAndScopes {
+        #[sqlx(try_from = "String")]
+        account: AccountId,
+        scope: Vec<String>,
+    }
+    let query = "
+        SELECT account, array_remove(array_agg(subscriber_scope.name), NULL) AS scope
         FROM subscriber
+        JOIN project ON project.id=subscriber.project
         LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
-        WHERE project=$1
+        WHERE project.project_id=$1
               AND get_address_lower(account)=ANY($2)
-        GROUP BY subscriber.id, project, account, sym_key, topic, expiry
+              AND subscriber.deleted_at IS NULL
+        GROUP BY account
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, NotifySubscriberInfoResult>(query)
+    let result = sqlx::query_as::<Postgres, ResultSubscriberAccountAndScopes>(query)
+        .bind(project_id.as_ref())
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_by_project_id_and_accounts", start, &result);
+    }
+    let projects = result?;
+    Ok(projects
+        .into_iter()
+        .map(|s| SubscriberAccountAndScopes {
+            account: s.account,
+            scope: parse_scopes_and_ignore_invalid(&s.scope),
+        })
+        .collect())
+}
+
+#[derive(Debug, FromRow)]
+struct TopicResult {
+    #[sqlx(try_from = "String")]
+    topic: Topic,
+}
+
+async fn scan_topics<'e>(
+    query: &'static str,
+    query_name: &'static str,
+    postgres: impl sqlx::PgExecutor<'e>,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, sqlx::error::Error> {
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, TopicResult>(query)
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(query_name, start, &result);
+    }
+    Ok(result?.into_iter().map(|p| p.topic).collect())
+}
+
+async fn scan_topics_with_timeout(
+    query: &'static str,
+    query_name: &'static str,
+    statement_timeout: Duration,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, ScanError> {
+    let mut txn = begin_with_statement_timeout(statement_timeout, postgres).await?;
+    let topics = scan_topics(query, query_name, &mut *txn, metrics).await?;
+    txn.commit().await?;
+    Ok(topics)
+}
+
+const SUBSCRIBER_TOPICS_QUERY: &str = "
+    SELECT topic
+    FROM subscriber
+    WHERE deleted_at IS NULL
+";
+
+// FIXME scaling: response not paginated
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_topics(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, sqlx::error::Error> {
+    scan_topics(
+        SUBSCRIBER_TOPICS_QUERY,
+        "get_subscriber_topics",
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Same as `get_subscriber_topics()` but the full scan is canceled after `statement_timeout`
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_topics_with_timeout(
+    statement_timeout: Duration,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, ScanError> {
+    scan_topics_with_timeout(
+        SUBSCRIBER_TOPICS_QUERY,
+        "get_subscriber_topics",
+        statement_timeout,
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Records the query in `metrics` once the stream is exhausted, as failed if any item was an error.
+/// A stream dropped before then isn't recorded. Each item is polled in the caller's current span.
+fn record_stream_query<'a, T: 'a>(
+    query_name: &'static str,
+    stream: impl Stream<Item = Result<T, sqlx::error::Error>> + 'a,
+    metrics: Option<&'a Metrics>,
+) -> impl Stream<Item = Result<T, sqlx::error::Error>> + 'a {
+    let span = tracing::Span::current();
+    let start = Instant::now();
+    futures::stream::unfold((Box::pin(stream), false), move |(mut stream, failed)| {
+        async move {
+            if let Some(item) = stream.next().await {
+                let failed = failed || item.is_err();
+                Some((item, (stream, failed)))
+            } else {
+                if let Some(metrics) = metrics {
+                    let result = if failed { Err(()) } else { Ok(()) };
+                    metrics.postgres_query_result(query_name, start, &result);
+                }
+                None
+            }
+        }
+        .instrument(span.clone())
+    })
+}
+
+/// Streaming version of `get_subscriber_topics()` that doesn't load every topic into memory at once
+#[instrument(skip(postgres, metrics))]
+pub fn get_subscriber_topics_stream<'a>(
+    postgres: &'a PgPool,
+    metrics: Option<&'a Metrics>,
+) -> impl Stream<Item = Result<Topic, sqlx::error::Error>> + 'a {
+    let stream = sqlx::query_as::<Postgres, TopicResult>(SUBSCRIBER_TOPICS_QUERY)
+        .fetch(postgres)
+        .map_ok(|p| p.topic);
+    record_stream_query("get_subscriber_topics_stream", stream, metrics)
+}
+
+const PROJECT_TOPICS_QUERY: &str = "
+    SELECT topic
+    FROM project
+";
+
+// FIXME scaling: response not paginated
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_topics(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, sqlx::error::Error> {
+    scan_topics(
+        PROJECT_TOPICS_QUERY,
+        "get_project_topics",
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Same as `get_project_topics()` but the full scan is canceled after `statement_timeout`
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_topics_with_timeout(
+    statement_timeout: Duration,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Topic>, ScanError> {
+    scan_topics_with_timeout(
+        PROJECT_TOPICS_QUERY,
+        "get_project_topics",
+        statement_timeout,
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Keyset pagination ordered by project `id`. Pass the returned `id` as `after` to get the next
+/// page; it is `None` once there are no more topics.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_topics_paginated(
+    after: Option<Uuid>,
+    limit: i64,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(Vec<Topic>, Option<Uuid>), sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct ProjectWithTopic {
+        id: Uuid,
+        #[sqlx(try_from = "String")]
+        topic: Topic,
+    }
+    let after_clause = if after.is_some() { "WHERE id > $2" } else { "" };
+    let query = &format!(
+        "
+        SELECT id, topic
+        FROM project
+        {after_clause}
+        ORDER BY id
+        LIMIT $1
+        "
+    );
+    let builder = sqlx::query_as::<Postgres, ProjectWithTopic>(query)
+        .bind(limit.clamp(0, LIST_PROJECTS_LIMIT_MAX));
+    let builder = if let Some(after) = after {
+        builder.bind(after)
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder.fetch_all(postgres).await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_project_topics_paginated", start, &result);
+    }
+    let projects = result?;
+    let last_id = projects.last().map(|p| p.id);
+    Ok((projects.into_iter().map(|p| p.topic).collect(), last_id))
+}
+
+/// Project and subscriber topics, deduplicated by the database. Soft-deleted subscribers excluded.
+// FIXME scaling: response not paginated
+#[instrument(skip(postgres, metrics))]
+pub async fn get_all_topics(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<HashSet<Topic>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct TopicResult {
+        #[sqlx(try_from = "String")]
+        topic: Topic,
+    }
+    let query = "
+        SELECT topic
+        FROM subscriber
+        WHERE deleted_at IS NULL
+        UNION
+        SELECT topic
+        FROM project
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, TopicResult>(query)
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_all_topics", start, &result);
+    }
+    let topics = result?;
+    Ok(topics.into_iter().map(|t| t.topic).collect())
+}
+
+#[derive(Debug)]
+pub struct SubscribeResponse {
+    pub id: Uuid,
+    pub account: AccountId,
+    pub topic: Topic,
+    pub inserted: bool,
+}
+
+pub struct UpsertSubscriberOutcome {
+    pub subscriber: SubscriberWithScope,
+    pub inserted: bool,
+}
+
+impl From<UpsertSubscriberOutcome> for SubscribeResponse {
+    fn from(val: UpsertSubscriberOutcome) -> Self {
+        SubscribeResponse {
+            id: val.subscriber.id,
+            account: val.subscriber.account,
+            topic: val.subscriber.topic,
+            inserted: val.inserted,
+        }
+    }
+}
+
+/// Maximum number of notification types a subscriber can have in its scope
+pub const MAX_SCOPES: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScopeError {
+    #[error("Scope must contain at least one notification type")]
+    Empty,
+
+    #[error("Scope contains {0} notification types, the maximum is {MAX_SCOPES}")]
+    TooMany(usize),
+
+    #[error("Scope contains notification types the project didn't declare: {0:?}")]
+    Undeclared(Vec<Uuid>),
+}
+
+/// How subscriber writes validate their scope. The default accepts an empty scope and doesn't
+/// check the project's declared notification types.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScopePolicy {
+    /// Fail with `ScopeError::Empty` if the scope is empty
+    pub require_non_empty: bool,
+    /// Fail with `ScopeError::Undeclared` if the project declared notification types and the scope
+    /// contains others. Otherwise the declared types aren't queried.
+    pub enforce_declared: bool,
+}
+
+fn validate_scope(scope: &HashSet<Uuid>, scope_policy: ScopePolicy) -> Result<(), ScopeError> {
+    if scope_policy.require_non_empty && scope.is_empty() {
+        return Err(ScopeError::Empty);
+    }
+    if scope.len() > MAX_SCOPES {
+        return Err(ScopeError::TooMany(scope.len()));
+    }
+    Ok(())
+}
+
+/// `AccountId` can be constructed from an `Arc<str>` without going through CAIP-10 validation, so
+/// check again before an account is written to the database
+fn validate_account(account: &AccountId) -> Result<(), Caip10Error> {
+    validate_caip_10(account.as_ref())
+}
+
+/// Replaces the notification types that the project declares. Once a project declares any types,
+/// subscriber writes with `ScopePolicy::enforce_declared` can only enable those. A project that
+/// declares none accepts any scope. Names and descriptions of types that remain declared are kept.
+#[instrument(skip(postgres, metrics))]
+pub async fn set_project_notification_types(
+    project: Uuid,
+    types: HashSet<Uuid>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), sqlx::error::Error> {
+    let mut txn = postgres.begin().await?;
+
+    let types = types.into_iter().collect::<Vec<_>>();
+
+    let query = "
+        DELETE FROM project_notification_type
+        WHERE project=$1
+              AND type<>ALL($2)
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(project)
+        .bind(&types)
+        .execute(&mut *txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("set_project_notification_types.delete", start, &result);
+    }
+    result?;
+
+    let query = "
+        INSERT INTO project_notification_type ( project, type )
+        SELECT $1 AS project, type FROM UNNEST($2) AS type
+        ON CONFLICT (project, type) DO NOTHING
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(project)
+        .bind(&types)
+        .execute(&mut *txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("set_project_notification_types.insert", start, &result);
+    }
+    result?;
+
+    txn.commit().await?;
+    Ok(())
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_notification_types(
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<HashSet<Uuid>, sqlx::error::Error> {
+    let query = "
+        SELECT type
+        FROM project_notification_type
+        WHERE project=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, Uuid>(query)
+        .bind(project)
+        .fetch_all(postgres)
+        .await
+        .map(|types| types.into_iter().collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_project_notification_types", start, &result);
+    }
+    result
+}
+
+#[derive(Debug, FromRow)]
+pub struct NotificationType {
+    /// The scope that subscribers enable to receive notifications of this type
+    #[sqlx(rename = "type")]
+    pub scope_id: Uuid,
+    /// None until labeled with `upsert_notification_type()`
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Sets the name and description of a notification type the project declared. Returns `false` if
+/// the type isn't declared; labeling never declares a type, since that would restrict subscribers
+/// to declared types. Declare types with `set_project_notification_types()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_notification_type(
+    project: Uuid,
+    scope_id: Uuid,
+    name: &str,
+    description: Option<&str>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<bool, sqlx::error::Error> {
+    let query = "
+        UPDATE project_notification_type
+        SET updated_at=now(),
+            name=$3,
+            description=$4
+        WHERE project=$1
+              AND type=$2
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(project)
+        .bind(scope_id)
+        .bind(name)
+        .bind(description)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("upsert_notification_type", start, &result);
+    }
+    Ok(result?.rows_affected() > 0)
+}
+
+/// Like `get_project_notification_types()` but with the names and descriptions for display
+#[instrument(skip(postgres, metrics))]
+pub async fn get_notification_types_for_project(
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<NotificationType>, sqlx::error::Error> {
+    let query = "
+        SELECT type, name, description
+        FROM project_notification_type
+        WHERE project=$1
+        ORDER BY type
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, NotificationType>(query)
+        .bind(project)
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_notification_types_for_project", start, &result);
+    }
+    result
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ValidateScopeError {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+/// Checks `scope` against the notification types that the project declared, without writing
+/// anything. Fails with `ScopeError::Undeclared` listing the unknown types.
+#[instrument(skip(postgres, metrics))]
+pub async fn validate_scopes_against_project(
+    project: Uuid,
+    scope: &HashSet<Uuid>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), ValidateScopeError> {
+    let mut txn = postgres.begin().await?;
+    let undeclared = get_undeclared_scopes(project, scope, &mut txn, metrics).await?;
+    txn.commit().await?;
+    check_undeclared_scopes(undeclared)?;
+    Ok(())
+}
+
+fn check_undeclared_scopes(mut undeclared: Vec<Uuid>) -> Result<(), ScopeError> {
+    if undeclared.is_empty() {
+        Ok(())
+    } else {
+        undeclared.sort();
+        Err(ScopeError::Undeclared(undeclared))
+    }
+}
+
+/// Always empty if the project didn't declare any notification types
+async fn get_undeclared_scopes(
+    project: Uuid,
+    scope: &HashSet<Uuid>,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<Uuid>, sqlx::error::Error> {
+    let query = "
+        SELECT scope
+        FROM UNNEST($2) AS scope
+        WHERE EXISTS (
+                  SELECT 1
+                  FROM project_notification_type
+                  WHERE project=$1
+              )
+              AND NOT EXISTS (
+                  SELECT 1
+                  FROM project_notification_type
+                  WHERE project=$1
+                        AND type=scope
+              )
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, Uuid>(query)
+        .bind(project)
+        .bind(scope.iter().copied().collect::<Vec<_>>())
+        .fetch_all(&mut **txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_undeclared_scopes", start, &result);
+    }
+    result
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpsertSubscriberError {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+
+    #[error("Invalid account: {0}")]
+    InvalidAccount(#[from] Caip10Error),
+
+    #[error("Subscriber topic {0} already in-use by another subscriber")]
+    TopicCollision(Topic),
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+/// Concurrent upserts of the same (project, account) don't interleave their scope replacements: the
+/// `ON CONFLICT DO UPDATE` locks the subscriber row until the transaction commits, so the next
+/// upsert waits before deleting the scopes.
+///
+/// Validates `scope` with the default `ScopePolicy`, see `upsert_subscriber_with_policy()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_subscriber(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<SubscribeResponse, UpsertSubscriberError> {
+    upsert_subscriber_with_policy(
+        project,
+        account,
+        scope,
+        ScopePolicy::default(),
+        notify_key,
+        notify_topic,
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Same as `upsert_subscriber()` but validates `scope` with `scope_policy`
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_subscriber_with_policy(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<SubscribeResponse, UpsertSubscriberError> {
+    upsert_subscriber_with_scope(
+        project,
+        account,
+        scope,
+        scope_policy,
+        notify_key,
+        notify_topic,
+        postgres,
+        metrics,
+    )
+    .await
+    .map(Into::into)
+}
+
+/// Same as `upsert_subscriber()` but returns the whole subscriber, so callers don't need to query
+/// it again
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_subscriber_with_scope(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<UpsertSubscriberOutcome, UpsertSubscriberError> {
+    validate_scope(&scope, scope_policy)?;
+    validate_account(&account)?;
+
+    let mut txn = postgres.begin().await?;
+    let subscriber = upsert_subscriber_impl(
+        project,
+        account,
+        scope,
+        scope_policy,
+        notify_key,
+        notify_topic,
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    txn.commit().await?;
+
+    Ok(subscriber)
+}
+
+/// Same as `upsert_subscriber()` but runs inside the caller's transaction so it can be composed
+/// with other writes
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(txn, metrics))]
+pub async fn upsert_subscriber_tx(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<SubscribeResponse, UpsertSubscriberError> {
+    validate_scope(&scope, scope_policy)?;
+    validate_account(&account)?;
+
+    Ok(upsert_subscriber_impl(
+        project,
+        account,
+        scope,
+        scope_policy,
+        notify_key,
+        notify_topic,
+        txn,
+        metrics,
+    )
+    .await?
+    .into())
+}
+
+async fn upsert_subscriber_impl(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<UpsertSubscriberOutcome, UpsertSubscriberError> {
+    #[derive(Debug, FromRow)]
+    struct UpsertSubscriberResult {
+        id: Uuid,
+        project: Uuid,
+        #[sqlx(try_from = "String")]
+        account: AccountId,
+        #[sqlx(try_from = "String")]
+        sym_key: SymKey,
+        #[sqlx(try_from = "String")]
+        topic: Topic,
+        expiry: DateTime<Utc>,
+        last_notified_at: Option<DateTime<Utc>>,
+        inserted_at: DateTime<Utc>,
+        updated_at: DateTime<Utc>,
+        inserted: bool,
+    }
+
+    if scope_policy.enforce_declared {
+        check_undeclared_scopes(get_undeclared_scopes(project, &scope, txn, metrics).await?)?;
+    }
+
+    // `xmax = 0`: https://stackoverflow.com/a/39204667
+
+    let query = "
+        INSERT INTO subscriber (
+            project,
+            account,
+            sym_key,
+            topic,
+            expiry
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (project, get_address_lower(account)) DO UPDATE SET
+            updated_at=now(),
+            expiry=$5,
+            deleted_at=NULL
+        RETURNING
+            id,
+            project,
+            account,
+            sym_key,
+            topic,
+            expiry,
+            last_notified_at,
+            inserted_at,
+            updated_at,
+            (xmax = 0) AS inserted
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, UpsertSubscriberResult>(query)
+        .bind(project)
+        .bind(account.as_ref())
+        .bind(SymKey::from(*notify_key).to_hex())
+        .bind(notify_topic.as_ref())
+        .bind(Utc::now() + chrono::Duration::days(30))
+        .fetch_one(&mut **txn)
+        .await
+        .map_err(|e| match e {
+            // An existing (project, account) takes the ON CONFLICT path above, so a unique
+            // violation means another subscriber already owns the topic. The topic is derived from
+            // the sym key, so a duplicate key may trip the sym_key constraint first.
+            sqlx::Error::Database(e)
+                if e.is_unique_violation()
+                    && (e.message().contains("subscriber_topic_key")
+                        || e.message().contains("subscriber_sym_key_key")) =>
+            {
+                UpsertSubscriberError::TopicCollision(notify_topic.clone())
+            }
+            other => other.into(),
+        });
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("upsert_subscriber", start, &result);
+    }
+    let subscriber = result?;
+
+    // Scope is replaced wholesale, so it's exactly what was passed in
+    update_subscriber_scope(
+        subscriber.id,
+        scope.clone(),
+        subscriber.inserted,
+        txn,
+        metrics,
+    )
+    .await?;
+
+    Ok(UpsertSubscriberOutcome {
+        subscriber: SubscriberWithScope {
+            id: subscriber.id,
+            project: subscriber.project,
+            account: subscriber.account,
+            sym_key: subscriber.sym_key,
+            topic: subscriber.topic,
+            scope,
+            expiry: subscriber.expiry,
+            last_notified_at: subscriber.last_notified_at,
+            created_at: subscriber.inserted_at,
+            updated_at: subscriber.updated_at,
+        },
+        inserted: subscriber.inserted,
+    })
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpdateSubscriberError {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+// TODO test idempotency
+/// Validates `scope` with the default `ScopePolicy`, see `update_subscriber_with_policy()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn update_subscriber(
+    subscriber: Uuid,
+    scope: HashSet<Uuid>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Subscriber, UpdateSubscriberError> {
+    update_subscriber_with_policy(subscriber, scope, ScopePolicy::default(), postgres, metrics)
+        .await
+}
+
+/// Same as `update_subscriber()` but validates `scope` with `scope_policy`
+#[instrument(skip(postgres, metrics))]
+pub async fn update_subscriber_with_policy(
+    subscriber: Uuid,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Subscriber, UpdateSubscriberError> {
+    validate_scope(&scope, scope_policy)?;
+
+    let mut txn = postgres.begin().await?;
+    let updated_subscriber =
+        update_subscriber_impl(subscriber, scope, scope_policy, None, &mut txn, metrics)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+    txn.commit().await?;
+
+    Ok(updated_subscriber)
+}
+
+/// Same as `update_subscriber()` but only applies if the subscriber's `updated_at` is still
+/// `expected_updated_at`, so that concurrent updates can't overwrite each other. Returns `None`
+/// without changing anything if the subscriber was updated since (or doesn't exist); re-read it
+/// and retry.
+#[instrument(skip(postgres, metrics))]
+pub async fn update_subscriber_if_unchanged(
+    subscriber: Uuid,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    expected_updated_at: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Option<Subscriber>, UpdateSubscriberError> {
+    validate_scope(&scope, scope_policy)?;
+
+    let mut txn = postgres.begin().await?;
+    let updated_subscriber = update_subscriber_impl(
+        subscriber,
+        scope,
+        scope_policy,
+        Some(expected_updated_at),
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    if updated_subscriber.is_some() {
+        txn.commit().await?;
+    }
+
+    Ok(updated_subscriber)
+}
+
+async fn update_subscriber_impl(
+    subscriber: Uuid,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    expected_updated_at: Option<DateTime<Utc>>,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<Option<Subscriber>, UpdateSubscriberError> {
+    let and_unchanged = if expected_updated_at.is_some() {
+        "AND updated_at=$3"
+    } else {
+        ""
+    };
+    let query = format!(
+        "
+        UPDATE subscriber
+        SET updated_at=now(),
+            expiry=$1
+        WHERE id=$2
+              {and_unchanged}
+        RETURNING *
+        "
+    );
+    let builder = sqlx::query_as::<_, Subscriber>(&query)
+        .bind(Utc::now() + chrono::Duration::days(30))
+        .bind(subscriber);
+    let builder = if let Some(expected_updated_at) = expected_updated_at {
+        builder.bind(expected_updated_at)
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder.fetch_optional(&mut **txn).await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("update_subscriber", start, &result);
+    }
+    let updated_subscriber = result?;
+    let Some(updated_subscriber) = updated_subscriber else {
+        return Ok(None);
+    };
+    assert_eq!(updated_subscriber.id, subscriber);
+
+    if scope_policy.enforce_declared {
+        check_undeclared_scopes(
+            get_undeclared_scopes(updated_subscriber.project, &scope, txn, metrics).await?,
+        )?;
+    }
+
+    update_subscriber_scope(updated_subscriber.id, scope, false, txn, metrics).await?;
+
+    Ok(Some(updated_subscriber))
+}
+
+/// Sets the expiry of the project's subscribers in `accounts` to `ttl` from now, without touching
+/// their scopes. Soft-deleted subscribers aren't renewed. Returns the number of subscribers
+/// renewed.
+#[instrument(skip(postgres, metrics))]
+pub async fn renew_subscribers_for_project(
+    project: Uuid,
+    accounts: &[AccountId],
+    ttl: chrono::Duration,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    let query = "
+        UPDATE subscriber
+        SET updated_at=now(),
+            expiry=$1
+        WHERE project=$2
+              AND get_address_lower(account)=ANY($3)
+              AND deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(Utc::now() + ttl)
+        .bind(project)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("renew_subscribers_for_project", start, &result);
+    }
+    Ok(result?.rows_affected())
+}
+
+/// Replaces the subscriber's scope, returning the number of scopes inserted. Set `is_new` if the
+/// subscriber was just inserted, in which case it shouldn't have had any scopes to replace.
+///
+/// A non-empty `scope` for a subscriber that doesn't exist fails on the
+/// `subscriber_scope.subscriber` foreign key. An empty `scope` for one succeeds with 0 inserted.
+async fn update_subscriber_scope(
+    subscriber: Uuid,
+    scope: HashSet<Uuid>,
+    is_new: bool,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscriber_scope
+        WHERE subscriber=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query(query)
+        .bind(subscriber)
+        .execute(&mut **txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("update_subscriber_scope.delete", start, &result);
+    }
+    let deleted = result?.rows_affected();
+    if is_new && deleted > 0 {
+        warn!("Replaced {deleted} scopes of subscriber {subscriber} that was expected to be new");
+    }
+
+    let query = "
+        INSERT INTO subscriber_scope ( subscriber, name )
+        SELECT $1 AS subscriber, name FROM UNNEST($2) AS name;
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .bind(scope.into_iter().collect::<Vec<_>>())
+        .execute(&mut **txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("update_subscriber_scope.insert", start, &result);
+    }
+    let inserted = result?.rows_affected();
+
+    Ok(inserted)
+}
+
+/// Adds to the subscriber's scope without touching existing entries. Idempotent. Fails without
+/// changing anything if the resulting scope doesn't pass `scope_policy`.
+#[instrument(skip(postgres, metrics))]
+pub async fn add_subscriber_scopes(
+    subscriber: Uuid,
+    to_add: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpdateSubscriberError> {
+    let query = "
+        INSERT INTO subscriber_scope ( subscriber, name )
+        SELECT $1 AS subscriber, name FROM UNNEST($2) AS name
+        ON CONFLICT (subscriber, name) DO NOTHING
+    ";
+    modify_subscriber_scopes(
+        subscriber,
+        query,
+        "add_subscriber_scopes",
+        to_add,
+        scope_policy,
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Removes from the subscriber's scope. Scopes the subscriber doesn't have are ignored. Fails
+/// without changing anything if the resulting scope doesn't pass `scope_policy`.
+#[instrument(skip(postgres, metrics))]
+pub async fn remove_subscriber_scopes(
+    subscriber: Uuid,
+    to_remove: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpdateSubscriberError> {
+    let query = "
+        DELETE FROM subscriber_scope
+        WHERE subscriber=$1
+              AND name=ANY($2::text[])
+    ";
+    modify_subscriber_scopes(
+        subscriber,
+        query,
+        "remove_subscriber_scopes",
+        to_remove,
+        scope_policy,
+        postgres,
+        metrics,
+    )
+    .await
+}
+
+/// Runs `query` (binding the subscriber as `$1` and `scopes` as `$2`) and validates the resulting
+/// scope in one transaction. Bumps the subscriber's `updated_at` first, which also locks the row so
+/// concurrent modifications validate one after another.
+async fn modify_subscriber_scopes(
+    subscriber: Uuid,
+    query: &'static str,
+    metrics_name: &'static str,
+    scopes: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpdateSubscriberError> {
+    let mut txn = postgres.begin().await?;
+
+    let touch_query = "
+        UPDATE subscriber
+        SET updated_at=now()
+        WHERE id=$1
+        RETURNING project
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, Uuid>(touch_query)
+        .bind(subscriber)
+        .fetch_optional(&mut *txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("modify_subscriber_scopes.touch", start, &result);
+    }
+    let project = result?.ok_or(sqlx::Error::RowNotFound)?;
+
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .bind(scopes.into_iter().collect::<Vec<_>>())
+        .execute(&mut *txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(metrics_name, start, &result);
+    }
+    result?;
+
+    let scope_query = "
+        SELECT name
+        FROM subscriber_scope
+        WHERE subscriber=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, String>(scope_query)
+        .bind(subscriber)
+        .fetch_all(&mut *txn)
+        .await
+        .map(|scope| parse_scopes_and_ignore_invalid(&scope));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("modify_subscriber_scopes.select", start, &result);
+    }
+    let scope = result?;
+
+    validate_scope(&scope, scope_policy)?;
+    if scope_policy.enforce_declared {
+        check_undeclared_scopes(get_undeclared_scopes(project, &scope, &mut txn, metrics).await?)?;
+    }
+
+    txn.commit().await?;
+    Ok(())
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_scopes(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<HashSet<Uuid>, sqlx::error::Error> {
+    let query = "
+        SELECT name
+        FROM subscriber_scope
+        WHERE subscriber=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, String>(query)
+        .bind(subscriber)
+        .fetch_all(postgres)
+        .await
+        .map(|scope| parse_scopes_and_ignore_invalid(&scope));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_scopes", start, &result);
+    }
+    result
+}
+
+/// Number of the project's subscribers that enabled each notification type. Scopes that aren't
+/// valid UUIDs are skipped, same as `parse_scopes_and_ignore_invalid()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_counts_by_scope(
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<HashMap<Uuid, i64>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct ScopeCountResult {
+        name: String,
+        count: i64,
+    }
+    let query = "
+        SELECT subscriber_scope.name, count(*)
+        FROM subscriber_scope
+        JOIN subscriber ON subscriber.id=subscriber_scope.subscriber
+        WHERE subscriber.project=$1
+              AND subscriber.deleted_at IS NULL
+        GROUP BY subscriber_scope.name
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, ScopeCountResult>(query)
+        .bind(project)
+        .fetch_all(postgres)
+        .await
+        .map(|counts| {
+            counts
+                .into_iter()
+                .filter_map(|c| Uuid::parse_str(&c.name).ok().map(|scope| (scope, c.count)))
+                .collect()
+        });
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_counts_by_scope", start, &result);
+    }
+    result
+}
+
+/// Notification types enabled by at least one of the project's subscribers
+#[instrument(skip(postgres, metrics))]
+pub async fn get_distinct_scopes_for_project(
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<HashSet<Uuid>, sqlx::error::Error> {
+    let query = "
+        SELECT DISTINCT subscriber_scope.name
+        FROM subscriber_scope
+        JOIN subscriber ON subscriber.id=subscriber_scope.subscriber
+        WHERE subscriber.project=$1
+              AND subscriber.deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, String>(query)
+        .bind(project)
+        .fetch_all(postgres)
+        .await
+        .map(|scope| parse_scopes_and_ignore_invalid(&scope));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_distinct_scopes_for_project", start, &result);
+    }
+    result
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MoveSubscriberToProjectError {
+    #[error("Account is already subscribed to project {0}")]
+    SubscriberConflict(Uuid),
+
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+/// Moves the subscriber to another project, keeping its ID so that scopes, expiry and keys carry
+/// over. Returns `RowNotFound` if there is no such subscriber.
+#[instrument(skip(postgres, metrics))]
+pub async fn move_subscriber_to_project(
+    subscriber: Uuid,
+    new_project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), MoveSubscriberToProjectError> {
+    let query = "
+        UPDATE subscriber
+        SET updated_at=now(),
+            project=$2
+        WHERE id=$1
+    ";
+    let mut txn = postgres.begin().await?;
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .bind(new_project)
+        .execute(&mut *txn)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(e)
+                if e.is_unique_violation()
+                    && e.message().contains("subscriber_project_account_key") =>
+            {
+                MoveSubscriberToProjectError::SubscriberConflict(new_project)
+            }
+            other => other.into(),
+        });
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("move_subscriber_to_project", start, &result);
+    }
+    let result = result?;
+    if result.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscriber(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscriber
+        WHERE id=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscriber", start, &result);
+    }
+    result?;
+    Ok(())
+}
+
+/// Scopes are removed by the `subscriber_scope.subscriber` foreign key's `ON DELETE CASCADE`
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscribers(
+    ids: &[Uuid],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    let query = "
+        DELETE FROM subscriber
+        WHERE id=ANY($1)
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(ids)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscribers", start, &result);
+    }
+    Ok(result?.rows_affected())
+}
+
+/// Relies on the `subscriber_scope.subscriber` foreign key being `ON DELETE CASCADE` to remove the
+/// scopes along with the subscribers in the same statement
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscribers_by_project(
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscriber
+        WHERE project=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(project)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscribers_by_project", start, &result);
+    }
+    Ok(result?.rows_affected())
+}
+
+/// Deletes all of the account's subscribers across all projects, including soft-deleted ones, e.g.
+/// to erase the account. Matches the account case-insensitively on the address like the other
+/// account lookups. Scopes and notifications are removed by their foreign keys' `ON DELETE CASCADE`
+/// in the same transaction. Returns the number of subscribers deleted.
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscribers_for_account(
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscriber
+        WHERE get_address_lower(account)=get_address_lower($1)
+    ";
+    let mut txn = postgres.begin().await?;
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(account.as_ref())
+        .execute(&mut *txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscribers_for_account", start, &result);
+    }
+    let result = result?;
+    txn.commit().await?;
+    Ok(result.rows_affected())
+}
+
+/// Scopes are removed by the `subscriber_scope.subscriber` foreign key's `ON DELETE CASCADE`.
+/// Soft-deleted subscribers aren't purged even once expired, so they can still be audited or
+/// restored; remove them with `delete_subscriber()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_expired_subscribers(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct DeleteResult {
+        count: i64,
+    }
+    let query = "
+        WITH deleted AS (
+            DELETE FROM subscriber
+            WHERE expiry <= now()
+                  AND deleted_at IS NULL
+            RETURNING *
+        )
+        SELECT count(*) FROM deleted
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, DeleteResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_expired_subscribers", start, &result);
+    }
+
+    Ok(result?.count)
+}
+
+/// Counts the subscribers that `delete_expired_subscribers()` would delete
+#[instrument(skip(postgres, metrics))]
+pub async fn get_expired_subscribers_count(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
+    let query = "
+        SELECT count(*)
+        FROM subscriber
+        WHERE expiry <= now()
+              AND deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_expired_subscribers_count", start, &result);
+    }
+
+    Ok(result?.count)
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_total_project_count(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
+    let query = "
+        SELECT count(*)
+        FROM project
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_total_project_count", start, &result);
+    }
+
+    Ok(result?.count)
+}
+
+/// Includes expired subscribers but not soft-deleted ones
+#[instrument(skip(postgres, metrics))]
+pub async fn get_total_subscriber_count(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
+    let query = "
+        SELECT count(*)
+        FROM subscriber
+        WHERE deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_total_subscriber_count", start, &result);
+    }
+
+    Ok(result?.count)
+}
+
+/// Number of unique accounts subscribed to at least one project. Unlike
+/// `get_total_subscriber_count()`, an account subscribed to many projects is counted once.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_distinct_subscriber_account_count(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
+    let query = "
+        SELECT count(DISTINCT get_address_lower(account))
+        FROM subscriber
+        WHERE deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_distinct_subscriber_account_count", start, &result);
+    }
+
+    Ok(result?.count)
+}
+
+/// Marks the subscriber as deleted while keeping the row (and its sym_key) so that it can be
+/// audited or restored with `undelete_subscriber()`. Use `delete_subscriber()` to remove it.
+/// Returns `false` if there was no subscriber with this ID.
+#[instrument(skip(postgres, metrics))]
+pub async fn soft_delete_subscriber(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<bool, sqlx::error::Error> {
+    let query = "
+        UPDATE subscriber
+        SET updated_at=now(),
+            deleted_at=now()
+        WHERE id=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("soft_delete_subscriber", start, &result);
+    }
+    Ok(result?.rows_affected() > 0)
+}
+
+/// Restores a subscriber deleted with `soft_delete_subscriber()`. Returns `false` if there was no
+/// subscriber with this ID.
+#[instrument(skip(postgres, metrics))]
+pub async fn undelete_subscriber(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<bool, sqlx::error::Error> {
+    let query = "
+        UPDATE subscriber
+        SET updated_at=now(),
+            deleted_at=NULL
+        WHERE id=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("undelete_subscriber", start, &result);
+    }
+    Ok(result?.rows_affected() > 0)
+}
+
+/// Records that a notification was just published to the subscriber. Doesn't touch `updated_at`
+/// since the subscription itself didn't change.
+#[instrument(skip(postgres, metrics))]
+pub async fn touch_subscriber_notified(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), sqlx::error::Error> {
+    let query = "
+        UPDATE subscriber
+        SET last_notified_at=now()
+        WHERE id=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(subscriber)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("touch_subscriber_notified", start, &result);
+    }
+    result?;
+    Ok(())
+}
+
+pub struct SubscriberWithScope {
+    pub id: Uuid,
+    pub project: Uuid,
+    pub account: AccountId,
+    pub sym_key: SymKey,
+    pub topic: Topic,
+    pub scope: HashSet<Uuid>,
+    pub expiry: DateTime<Utc>,
+    /// When a notification was last published to the subscriber. None if never.
+    pub last_notified_at: Option<DateTime<Utc>>,
+    /// When the subscriber was first inserted. Not reset by re-subscribing
+    pub created_at: DateTime<Utc>,
+    /// When the subscriber was last changed, e.g. by re-subscribing or updating its scope
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Selects the columns of `SubscriberWithScopeResult`. Follow it with the `WHERE` clause and then
+/// `SUBSCRIBER_WITH_SCOPE_GROUP_BY`.
+const SUBSCRIBER_WITH_SCOPE_SELECT: &str = "
+        SELECT subscriber.id, project, account, sym_key, array_remove(array_agg(subscriber_scope.name), NULL) AS \
+                 scope, topic, expiry, last_notified_at, subscriber.inserted_at, subscriber.updated_at
+        FROM subscriber
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id";
+
+const SUBSCRIBER_WITH_SCOPE_GROUP_BY: &str = "
+        GROUP BY subscriber.id, project, account, sym_key, topic, expiry, last_notified_at, \
+                 subscriber.inserted_at, subscriber.updated_at";
+
+#[derive(FromRow)]
+pub struct SubscriberWithScopeResult {
+    pub id: Uuid,
+    pub project: Uuid,
+    #[sqlx(try_from = "String")]
+    pub account: AccountId,
+    #[sqlx(try_from = "String")]
+    pub sym_key: SymKey,
+    #[sqlx(try_from = "String")]
+    pub topic: Topic,
+    pub scope: Vec<String>,
+    pub expiry: DateTime<Utc>,
+    pub last_notified_at: Option<DateTime<Utc>>,
+    #[sqlx(rename = "inserted_at")]
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<SubscriberWithScopeResult> for SubscriberWithScope {
+    fn from(val: SubscriberWithScopeResult) -> Self {
+        SubscriberWithScope {
+            id: val.id,
+            project: val.project,
+            account: val.account,
+            sym_key: val.sym_key,
+            topic: val.topic,
+            scope: parse_scopes_and_ignore_invalid(&val.scope),
+            expiry: val.expiry,
+            last_notified_at: val.last_notified_at,
+            created_at: val.created_at,
+            updated_at: val.updated_at,
+        }
+    }
+}
+
+/// Returns `RowNotFound` if there is no subscriber with this topic. Use `try_get_subscriber_by_topic()`
+/// when an unknown topic is expected.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_by_topic(
+    topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<SubscriberWithScope, sqlx::error::Error> {
+    try_get_subscriber_by_topic(topic, postgres, metrics)
+        .await?
+        .ok_or(sqlx::Error::RowNotFound)
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn try_get_subscriber_by_topic(
+    topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Option<SubscriberWithScope>, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE topic=$1
+              AND deleted_at IS NULL
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        "
+    );
+    let start = Instant::now();
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+            .bind(topic.as_ref())
+            .fetch_optional(postgres)
+    })
+    .await
+    .map(|subscriber| subscriber.map(Into::into));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_by_topic", start, &result);
+    }
+    result
+}
+
+/// `SubscriberWithScope` along with the project fields needed to respond on the subscriber's topic
+pub struct SubscriberWithScopeAndProject {
+    pub subscriber: SubscriberWithScope,
+    /// App domain of the project the subscriber belongs to
+    pub app_domain: String,
+    /// Authentication key of the project the subscriber belongs to
+    pub authentication_public_key: String,
+}
+
+#[derive(FromRow)]
+struct SubscriberWithScopeAndProjectResult {
+    #[sqlx(flatten)]
+    pub subscriber: SubscriberWithScopeResult,
+    pub app_domain: String,
+    pub authentication_public_key: String,
+}
+
+impl From<SubscriberWithScopeAndProjectResult> for SubscriberWithScopeAndProject {
+    fn from(val: SubscriberWithScopeAndProjectResult) -> Self {
+        SubscriberWithScopeAndProject {
+            subscriber: val.subscriber.into(),
+            app_domain: val.app_domain,
+            authentication_public_key: val.authentication_public_key,
+        }
+    }
+}
+
+/// Like `try_get_subscriber_by_topic()` but also returns the project's `app_domain` and
+/// `authentication_public_key` in the same query. None if there is no subscriber with this topic.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_with_project_by_topic(
+    topic: Topic,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Option<SubscriberWithScopeAndProject>, sqlx::error::Error> {
+    let query = "
+        SELECT subscriber.id, subscriber.project, account, sym_key, array_remove(array_agg(subscriber_scope.name), NULL) AS \
+                 scope, subscriber.topic, expiry, last_notified_at, subscriber.inserted_at, subscriber.updated_at, \
+                 project.app_domain, project.authentication_public_key
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE subscriber.topic=$1
+              AND deleted_at IS NULL
+        GROUP BY subscriber.id, subscriber.project, account, sym_key, subscriber.topic, expiry, last_notified_at, \
+                 subscriber.inserted_at, subscriber.updated_at, project.app_domain, project.authentication_public_key
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithScopeAndProjectResult>(query)
+        .bind(topic.as_ref())
+        .fetch_optional(postgres)
+        .await
+        .map(|subscriber| subscriber.map(Into::into));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_with_project_by_topic", start, &result);
+    }
+    result
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_by_id(
+    id: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<SubscriberWithScope, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE subscriber.id=$1
+              AND deleted_at IS NULL
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        "
+    );
+    let start = Instant::now();
+    let result = with_retry(READ_RETRIES, READ_RETRY_BASE_DELAY, || {
+        sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+            .bind(id)
+            .fetch_one(postgres)
+    })
+    .await
+    .map(Into::into);
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_by_id", start, &result);
+    }
+    result
+}
+
+/// Whether the account has an unexpired subscription to the project, without loading the subscriber
+#[instrument(skip(postgres, metrics))]
+pub async fn is_account_subscribed(
+    project: Uuid,
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<bool, sqlx::error::Error> {
+    let query = "
+        SELECT EXISTS(
+            SELECT 1
+            FROM subscriber
+            WHERE project=$1
+                  AND get_address_lower(account)=get_address_lower($2)
+                  AND expiry > now()
+                  AND deleted_at IS NULL
+        )
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, bool>(query)
+        .bind(project)
+        .bind(account.as_ref())
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("is_account_subscribed", start, &result);
+    }
+    result
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_by_project_and_account(
+    project: Uuid,
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Option<SubscriberWithScope>, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE project=$1
+              AND get_address_lower(account)=get_address_lower($2)
+              AND deleted_at IS NULL
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        "
+    );
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+        .bind(project)
+        .bind(account.as_ref())
+        .fetch_optional(postgres)
+        .await
+        .map(|result| result.map(Into::into));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_by_project_and_account", start, &result);
+    }
+    result
+}
+
+/// Subscribers of the project that have `scope` enabled. The full scope of each subscriber is
+/// returned, not just `scope`.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_for_project_with_scope(
+    project: Uuid,
+    scope: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithScope>, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE project=$1
+              AND deleted_at IS NULL
+              AND EXISTS (
+                  SELECT 1
+                  FROM subscriber_scope
+                  WHERE subscriber_scope.subscriber=subscriber.id
+                        AND subscriber_scope.name=$2
+              )
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        "
+    );
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+        .bind(project)
+        .bind(scope.to_string())
+        .fetch_all(postgres)
+        .await
+        .map(|vec| vec.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_for_project_with_scope", start, &result);
+    }
+    result
+}
+
+/// Subscribers of the project whose `expiry` is in `[from, to)`, e.g. to send renewal reminders.
+/// Backed by the `subscriber_project_expiry` index.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_expiring_between(
+    project: Uuid,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithScope>, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE project=$1
+              AND expiry >= $2
+              AND expiry < $3
+              AND deleted_at IS NULL
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        "
+    );
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+        .bind(project)
+        .bind(from)
+        .bind(to)
+        .fetch_all(postgres)
+        .await
+        .map(|vec| vec.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_expiring_between", start, &result);
+    }
+    result
+}
+
+pub const EXPIRING_SUBSCRIBERS_LIMIT_MAX: i64 = 1000;
+
+/// Unexpired subscribers across all projects whose `expiry` is before `threshold`, soonest first.
+/// Renewing a subscriber moves its expiry past `threshold`, so calling this again returns the next
+/// batch.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_expiring_before(
+    threshold: DateTime<Utc>,
+    limit: i64,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithScope>, sqlx::error::Error> {
+    let query = format!(
+        "
+        {SUBSCRIBER_WITH_SCOPE_SELECT}
+        WHERE expiry > now()
+              AND expiry < $1
+              AND deleted_at IS NULL
+        {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+        ORDER BY expiry ASC, subscriber.id ASC
+        LIMIT $2
+        "
+    );
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&query)
+        .bind(threshold)
+        .bind(limit.clamp(0, EXPIRING_SUBSCRIBERS_LIMIT_MAX))
+        .fetch_all(postgres)
+        .await
+        .map(|vec| vec.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_expiring_before", start, &result);
+    }
+    result
+}
+
+pub struct NotifySubscriberInfo {
+    pub id: Uuid,
+    pub account: AccountId,
+    pub scope: HashSet<Uuid>,
+}
+
+#[derive(FromRow)]
+pub struct NotifySubscriberInfoResult {
+    pub id: Uuid,
+    #[sqlx(try_from = "String")]
+    pub account: AccountId,
+    pub scope: Vec<String>,
+}
+
+impl From<NotifySubscriberInfoResult> for NotifySubscriberInfo {
+    fn from(val: NotifySubscriberInfoResult) -> Self {
+        NotifySubscriberInfo {
+            id: val.id,
+            account: val.account,
+            scope: parse_scopes_and_ignore_invalid(&val.scope),
+        }
+    }
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_for_project_in(
+    project: Uuid,
+    accounts: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<NotifySubscriberInfo>, sqlx::error::Error> {
+    let query = "
+        SELECT subscriber.id, account, array_remove(array_agg(subscriber_scope.name), NULL) AS scope
+        FROM subscriber
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE project=$1
+              AND get_address_lower(account)=ANY($2)
+              AND deleted_at IS NULL
+        GROUP BY subscriber.id, project, account, sym_key, topic, expiry
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, NotifySubscriberInfoResult>(query)
+        .bind(project)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await
+        .map(|vec| vec.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_for_project_in", start, &result);
+    }
+    result
+}
+
+/// Only the topic and key needed to encrypt and publish to each of the project's subscribers in
+/// `accounts`, without aggregating scopes. Matches the same subscribers as
+/// `get_subscribers_for_project_in()`.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_delivery_targets(
+    project: Uuid,
+    accounts: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<(Topic, SymKey)>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct DeliveryTargetResult {
+        #[sqlx(try_from = "String")]
+        topic: Topic,
+        #[sqlx(try_from = "String")]
+        sym_key: SymKey,
+    }
+    let query = "
+        SELECT topic, sym_key
+        FROM subscriber
+        WHERE project=$1
+              AND get_address_lower(account)=ANY($2)
+              AND deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, DeliveryTargetResult>(query)
+        .bind(project)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await
+        .map(|targets| {
+            targets
+                .into_iter()
+                .map(|target| (target.topic, target.sym_key))
+                .collect()
+        });
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_delivery_targets", start, &result);
+    }
+    result
+}
+
+#[derive(Debug, FromRow)]
+pub struct NotifyTarget {
+    #[sqlx(try_from = "String")]
+    pub account: AccountId,
+    #[sqlx(try_from = "String")]
+    pub sym_key: SymKey,
+    #[sqlx(try_from = "String")]
+    pub topic: Topic,
+}
+
+/// Like `get_subscriber_delivery_targets()` but also returns the account, for fan-out that needs to
+/// know who each target is. Use `get_subscribers_for_project_in()` if the scopes are needed.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_notify_targets_for_project_in(
+    project: Uuid,
+    accounts: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<NotifyTarget>, sqlx::error::Error> {
+    let query = "
+        SELECT account, sym_key, topic
+        FROM subscriber
+        WHERE project=$1
+              AND get_address_lower(account)=ANY($2)
+              AND deleted_at IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, NotifyTarget>(query)
+        .bind(project)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_notify_targets_for_project_in", start, &result);
+    }
+    result
+}
+
+/// All of the project's subscribers, streamed as the database produces them so that memory use
+/// doesn't grow with the size of the project
+#[instrument(skip(postgres, metrics))]
+pub fn stream_subscribers_for_project<'a>(
+    project: Uuid,
+    postgres: &'a PgPool,
+    metrics: Option<&'a Metrics>,
+) -> impl Stream<Item = Result<SubscriberWithScope, sqlx::error::Error>> + 'a {
+    // The stream borrows the query, so it's built once instead of per call
+    static QUERY: Lazy<String> = Lazy::new(|| {
+        format!(
+            "
+            {SUBSCRIBER_WITH_SCOPE_SELECT}
+            WHERE project=$1
+                  AND deleted_at IS NULL
+            {SUBSCRIBER_WITH_SCOPE_GROUP_BY}
+            "
+        )
+    });
+    let stream = sqlx::query_as::<Postgres, SubscriberWithScopeResult>(&QUERY)
+        .bind(project)
+        .fetch(postgres)
+        .map_ok(Into::into);
+    record_stream_query("stream_subscribers_for_project", stream, metrics)
+}
+
+pub const SUBSCRIBERS_PAGE_LIMIT_MAX: i64 = 1000;
+
+/// Keyset paginated version of `get_subscribers_for_project_in()` ordered by subscriber `id`. Pass
+/// the returned `id` as `after` to get the next page; it is `None` once there are no more
+/// subscribers.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscribers_for_project_in_paginated(
+    project: Uuid,
+    accounts: &[AccountId],
+    after: Option<Uuid>,
+    limit: i64,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(Vec<NotifySubscriberInfo>, Option<Uuid>), sqlx::error::Error> {
+    let after_clause = if after.is_some() {
+        "AND subscriber.id > $4"
+    } else {
+        ""
+    };
+    let query = &format!(
+        "
+        SELECT subscriber.id, account, array_remove(array_agg(subscriber_scope.name), NULL) AS scope
+        FROM subscriber
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE project=$1
+              AND get_address_lower(account)=ANY($2)
+              AND deleted_at IS NULL
+              {after_clause}
+        GROUP BY subscriber.id, project, account, sym_key, topic, expiry
+        ORDER BY subscriber.id
+        LIMIT $3
+        "
+    );
+    let builder = sqlx::query_as::<Postgres, NotifySubscriberInfoResult>(query)
+        .bind(project)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .bind(limit.clamp(0, SUBSCRIBERS_PAGE_LIMIT_MAX));
+    let builder = if let Some(after) = after {
+        builder.bind(after)
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder.fetch_all(postgres).await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscribers_for_project_in_paginated", start, &result);
+    }
+    let subscribers = result?;
+    let last_id = subscribers.last().map(|s| s.id);
+    Ok((subscribers.into_iter().map(Into::into).collect(), last_id))
+}
+
+/// The `candidates` that aren't subscribed to the project. Like `is_account_subscribed()`, an
+/// expired subscription counts as not subscribed.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_unsubscribed_accounts(
+    project: Uuid,
+    candidates: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<AccountId>, sqlx::error::Error> {
+    if candidates.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    #[derive(Debug, FromRow)]
+    struct AccountResult {
+        #[sqlx(try_from = "String")]
+        account: AccountId,
+    }
+    let query = "
+        SELECT candidate.account
+        FROM UNNEST($2::text[]) AS candidate(account)
+        LEFT JOIN subscriber ON subscriber.project=$1
+            AND get_address_lower(subscriber.account)=get_address_lower(candidate.account)
+            AND subscriber.expiry > now()
+            AND subscriber.deleted_at IS NULL
+        WHERE subscriber.id IS NULL
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, AccountResult>(query)
+        .bind(project)
+        .bind(
+            candidates
+                .iter()
+                .map(|account| account.as_ref().to_owned())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await
+        .map(|accounts| accounts.into_iter().map(|a| a.account).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_unsubscribed_accounts", start, &result);
+    }
+    result
+}
+
+pub struct SubscriberWithProject {
+    /// App domain that the subscription refers to
+    pub app_domain: String,
+    /// Authentication key used for authenticating topic JWTs and setting JWT aud field
+    pub authentication_public_key: String,
+    /// Project that the subscription refers to, for calling project-scoped APIs
+    pub project_id: ProjectId,
+    /// CAIP-10 account. Lets callers querying several accounts at once regroup the results
+    pub account: AccountId,
+    /// Symetric key used for notify topic. sha256 to get notify topic to manage
+    /// the subscription and call wc_notifySubscriptionUpdate and
+    /// wc_notifySubscriptionDelete
+    pub sym_key: SymKey,
+    /// Array of notification types enabled for this subscription
+    pub scope: HashSet<Uuid>,
+    /// Unix timestamp of expiration
+    pub expiry: DateTime<Utc>,
+    /// Number of unread notifications
+    pub unread_notification_count: u64,
+    /// When the subscription was last changed. Can be used as a sync cursor
+    pub updated_at: DateTime<Utc>,
+    /// When the subscription was first created
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct SubscriberWithProjectResult {
+    pub app_domain: String,
+    pub authentication_public_key: String,
+    #[sqlx(try_from = "String")]
+    pub project_id: ProjectId,
+    #[sqlx(try_from = "String")]
+    pub account: AccountId,
+    #[sqlx(try_from = "String")]
+    pub sym_key: SymKey,
+    pub scope: Vec<String>,
+    pub expiry: DateTime<Utc>,
+    pub unread_notification_count: i64,
+    pub updated_at: DateTime<Utc>,
+    #[sqlx(rename = "inserted_at")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<SubscriberWithProjectResult> for SubscriberWithProject {
+    fn from(val: SubscriberWithProjectResult) -> Self {
+        SubscriberWithProject {
+            app_domain: val.app_domain,
+            authentication_public_key: val.authentication_public_key,
+            project_id: val.project_id,
+            account: val.account,
+            sym_key: val.sym_key,
+            scope: parse_scopes_and_ignore_invalid(&val.scope),
+            expiry: val.expiry,
+            unread_notification_count: val
+                .unread_notification_count
+                .try_into()
+                .map_err(|e| {
+                    // This error shouldn't happen so not bothering with returning Result here
+                    // But if it does, this is a bug and apply use defensive programming
+                    error!("Error converting unread_notification_count from i64 to u64: {e}");
+                    e
+                })
+                .unwrap_or(0),
+            updated_at: val.updated_at,
+            created_at: val.created_at,
+        }
+    }
+}
+
+fn parse_scopes_and_ignore_invalid(scopes: &[String]) -> HashSet<Uuid> {
+    scopes
+        .iter()
+        .filter_map(|s| Uuid::parse_str(s).ok())
+        .collect()
+}
+
+/// Subscriptions that have passed their expiry are only returned if `include_expired` is set
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriptions_by_account_and_maybe_app(
+    account: AccountId,
+    app_domain: Option<&str>,
+    include_expired: bool,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
+    let and_app = if app_domain.is_some() {
+        "AND project.app_domain=$2"
+    } else {
+        ""
+    };
+    let and_not_expired = if include_expired {
+        ""
+    } else {
+        "AND expiry > now()"
+    };
+    let query = format!(
+        "
+        SELECT
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at,
+            (
+                SELECT COUNT(*)
+                FROM subscriber_notification
+                WHERE
+                    subscriber=subscriber.id
+                    AND is_read=false
+            ) AS unread_notification_count
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE
+            get_address_lower(account)=get_address_lower($1)
+            AND subscriber.deleted_at IS NULL
+            {and_app}
+            {and_not_expired}
+        GROUP BY
+            subscriber.id,
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at
+        "
+    );
+    let builder =
+        sqlx::query_as::<Postgres, SubscriberWithProjectResult>(&query).bind(account.as_ref());
+    let builder = if let Some(app_domain) = app_domain {
+        builder.bind(normalize_app_domain_for_lookup(app_domain))
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder
+        .fetch_all(postgres)
+        .await
+        .map(|result| result.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriptions_by_account_and_maybe_app", start, &result);
+    }
+
+    result
+}
+
+/// All of the account's unexpired subscriptions across apps. Use
+/// `get_subscriptions_by_account_and_maybe_app()` with `include_expired` for the full history.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_active_subscriptions_by_account(
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
+    get_subscriptions_by_account_and_maybe_app(account, None, false, postgres, metrics).await
+}
+
+/// Same as `get_subscriptions_by_account_and_maybe_app()` with an app and without
+/// `include_expired`, for callers that have the project ID rather than the app domain
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriptions_by_account_and_project_id(
+    account: AccountId,
+    project_id: ProjectId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
+    let query = "
+        SELECT
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at,
+            (
+                SELECT COUNT(*)
+                FROM subscriber_notification
+                WHERE
+                    subscriber=subscriber.id
+                    AND is_read=false
+            ) AS unread_notification_count
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE
+            get_address_lower(account)=get_address_lower($1)
+            AND project.project_id=$2
+            AND expiry > now()
+            AND subscriber.deleted_at IS NULL
+        GROUP BY
+            subscriber.id,
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithProjectResult>(query)
+        .bind(account.as_ref())
+        .bind(project_id.as_ref())
+        .fetch_all(postgres)
+        .await
+        .map(|result| result.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(
+            "get_subscriptions_by_account_and_project_id",
+            start,
+            &result,
+        );
+    }
+
+    result
+}
+
+/// Like `get_subscriptions_by_account_and_maybe_app()` but for several accounts at once, e.g. all
+/// the accounts of a wallet. Use `SubscriberWithProject::account` to regroup the results.
+/// Subscriptions that have passed their expiry are not returned.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriptions_by_accounts(
+    accounts: &[AccountId],
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
+    if accounts.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let query = "
+        SELECT
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at,
+            (
+                SELECT COUNT(*)
+                FROM subscriber_notification
+                WHERE
+                    subscriber=subscriber.id
+                    AND is_read=false
+            ) AS unread_notification_count
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE
+            get_address_lower(account)=ANY($1)
+            AND expiry > now()
+            AND subscriber.deleted_at IS NULL
+        GROUP BY
+            subscriber.id,
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithProjectResult>(query)
+        .bind(
+            accounts
+                .iter()
+                .map(|account| get_address_from_account(account).to_ascii_lowercase())
+                .collect::<Vec<_>>(),
+        )
+        .fetch_all(postgres)
+        .await
+        .map(|result| result.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriptions_by_accounts", start, &result);
+    }
+
+    result
+}
+
+/// Number of the account's unexpired subscriptions per app domain, without aggregating scopes
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscription_counts_by_app_for_account(
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<(String, i64)>, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct AppCountResult {
+        app_domain: String,
+        count: i64,
+    }
+    let query = "
+        SELECT app_domain, count(*)
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        WHERE get_address_lower(account)=get_address_lower($1)
+              AND expiry > now()
+              AND deleted_at IS NULL
+        GROUP BY app_domain
+        ORDER BY app_domain
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, AppCountResult>(query)
+        .bind(account.as_ref())
+        .fetch_all(postgres)
+        .await
+        .map(|counts| {
+            counts
+                .into_iter()
+                .map(|c| (c.app_domain, c.count))
+                .collect()
+        });
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscription_counts_by_app_for_account", start, &result);
+    }
+    result
+}
+
+/// Only returns subscriptions that changed after `updated_after`, if provided. Pass the greatest
+/// `updated_at` of the previous response to sync incrementally. Soft-deleted subscriptions are
+/// never returned, so detect removals with a full sync (`updated_after` of None).
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriptions_by_account_updated_after(
+    account: AccountId,
+    updated_after: Option<DateTime<Utc>>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
+    let and_updated_after = if updated_after.is_some() {
+        "AND subscriber.updated_at>$2"
+    } else {
+        ""
+    };
+    let query = format!(
+        "
+        SELECT
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at,
+            (
+                SELECT COUNT(*)
+                FROM subscriber_notification
+                WHERE
+                    subscriber=subscriber.id
+                    AND is_read=false
+            ) AS unread_notification_count
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE
+            get_address_lower(account)=get_address_lower($1)
+            AND subscriber.deleted_at IS NULL
+            {and_updated_after}
+        GROUP BY
+            subscriber.id,
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at
+        "
+    );
+    let builder =
+        sqlx::query_as::<Postgres, SubscriberWithProjectResult>(&query).bind(account.as_ref());
+    let builder = if let Some(updated_after) = updated_after {
+        builder.bind(updated_after)
+    } else {
+        builder
+    };
+    let start = Instant::now();
+    let result = builder
+        .fetch_all(postgres)
+        .await
+        .map(|result| result.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriptions_by_account_updated_after", start, &result);
+    }
+
+    result
+}
+
+pub struct SubscriberWithProjectAndFlags {
+    pub subscriber: SubscriberWithProject,
+    /// If the subscription expires within the threshold provided to the query
+    pub expiring_soon: bool,
+}
+
+#[derive(FromRow)]
+struct SubscriberWithProjectAndFlagsResult {
+    #[sqlx(flatten)]
+    pub subscriber: SubscriberWithProjectResult,
+    pub expiring_soon: bool,
+}
+
+impl From<SubscriberWithProjectAndFlagsResult> for SubscriberWithProjectAndFlags {
+    fn from(val: SubscriberWithProjectAndFlagsResult) -> Self {
+        SubscriberWithProjectAndFlags {
+            subscriber: val.subscriber.into(),
+            expiring_soon: val.expiring_soon,
+        }
+    }
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriptions_by_account_with_flags(
+    account: AccountId,
+    soon_threshold: chrono::Duration,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriberWithProjectAndFlags>, sqlx::error::Error> {
+    let query = "
+        SELECT
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at,
+            (
+                SELECT COUNT(*)
+                FROM subscriber_notification
+                WHERE
+                    subscriber=subscriber.id
+                    AND is_read=false
+            ) AS unread_notification_count,
+            expiry < now() + $2 AS expiring_soon
+        FROM subscriber
+        JOIN project ON project.id=subscriber.project
+        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
+        WHERE get_address_lower(account)=get_address_lower($1)
+              AND subscriber.deleted_at IS NULL
+        GROUP BY
+            subscriber.id,
+            app_domain,
+            project.authentication_public_key,
+            project.project_id,
+            account,
+            sym_key,
+            expiry,
+            subscriber.updated_at,
+            subscriber.inserted_at
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberWithProjectAndFlagsResult>(query)
+        .bind(account.as_ref())
+        .bind(soon_threshold)
+        .fetch_all(postgres)
+        .await
+        .map(|result| result.into_iter().map(Into::into).collect());
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriptions_by_account_with_flags", start, &result);
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    Active,
+    Expired,
+}
+
+pub struct SubscriptionDetail {
+    pub subscription: SubscriberWithProject,
+    pub status: SubscriptionStatus,
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_account_subscription_details(
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriptionDetail>, sqlx::error::Error> {
+    let subscriptions =
+        get_subscriptions_by_account_and_maybe_app(account, None, true, postgres, metrics).await?;
+    let now = Utc::now();
+    Ok(subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let status = if subscription.expiry > now {
+                SubscriptionStatus::Active
+            } else {
+                SubscriptionStatus::Expired
+            };
+            SubscriptionDetail {
+                subscription,
+                status,
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpsertSubscriptionWatcherError {
+    #[error("Subscription watcher limit reached")]
+    LimitReached,
+
+    #[error("Subscription watcher sym_key in-use by another watcher")]
+    SymKeyConflict,
+
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+/// Inserts the watcher, or updates the existing watcher with the same `did_key`. Fails with
+/// `SymKeyConflict` if an unexpired watcher with a different `did_key` already uses `sym_key`,
+/// rather than creating a second watcher that decrypts to the same channel. Expired watchers don't
+/// conflict since nothing is delivered to them.
+///
+/// Upserts of the same `sym_key` are serialized by an advisory lock on it, so of two concurrent
+/// upserts with different `did_key`s exactly one succeeds.
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_subscription_watcher(
+    account: AccountId,
+    project: Option<Uuid>,
+    did_key: &str,
+    sym_key: &str,
+    expiry: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpsertSubscriptionWatcherError> {
+    let mut txn = postgres.begin().await?;
+    upsert_subscription_watcher_impl(
+        account, project, did_key, sym_key, expiry, &mut txn, metrics,
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(())
+}
+
+async fn upsert_subscription_watcher_impl(
+    account: AccountId,
+    project: Option<Uuid>,
+    did_key: &str,
+    sym_key: &str,
+    expiry: DateTime<Utc>,
+    txn: &mut sqlx::Transaction<'_, Postgres>,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpsertSubscriptionWatcherError> {
+    // The check below doesn't lock anything, so without this two upserts of the same sym_key with
+    // different did_keys could both pass it. Released when the transaction ends.
+    let query = "
+        SELECT pg_advisory_xact_lock(hashtext($1))
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(sym_key)
+        .execute(&mut **txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("upsert_subscription_watcher.lock", start, &result);
+    }
+    result?;
+
+    let query = "
+        SELECT EXISTS(
+            SELECT 1
+            FROM subscription_watcher
+            WHERE sym_key=$1
+                  AND did_key<>$2
+                  AND expiry > now()
+        )
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, bool>(query)
+        .bind(sym_key)
+        .bind(did_key)
+        .fetch_one(&mut **txn)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(
+            "upsert_subscription_watcher.sym_key_conflict",
+            start,
+            &result,
+        );
+    }
+    let sym_key_conflict = result?;
+    if sym_key_conflict {
+        return Err(UpsertSubscriptionWatcherError::SymKeyConflict);
+    }
+
+    let query = "
+        INSERT INTO subscription_watcher (
+            account,
+            project,
+            did_key,
+            sym_key,
+            expiry
+        )
+        SELECT $1, $2, $3, $4, $5 WHERE (
+            SELECT COUNT(*)
+            FROM subscription_watcher
+            WHERE get_address_lower(account)=get_address_lower($1)
+                  AND project=$2
+        ) < $6
+        ON CONFLICT (did_key) DO UPDATE SET
+            updated_at=now(),
+            account=$1,
+            project=$2,
+            sym_key=$4,
+            expiry=$5
+        RETURNING *
+    ";
+    let start = Instant::now();
+    // https://stackoverflow.com/a/48730873
+    // Allow phantom reads; going above the watcher limit is not a big deal and handling
+    // serialization errors is not worth the effort
+    // sqlx::query::<Postgres>("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
+    //     .execute(&mut **txn)
+    //     .await?;
+    let result = sqlx::query_as::<Postgres, ()>(query)
+        .bind(account.as_ref())
         .bind(project)
-        .bind(
-            accounts
-                .iter()
-                .map(|account| get_address_from_account(account).to_ascii_lowercase())
-                .collect::<Vec<_>>(),
-        )
-        .fetch_all(postgres)
-        .await
-        .map(|vec| vec.into_iter().map(Into::into).collect());
+        .bind(did_key)
+        .bind(sym_key)
+        .bind(expiry)
+        .bind(SUBSCRIPTION_WATCHER_LIMIT)
+        .fetch_optional(&mut **txn)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscribers_for_project_in", start);
+        metrics.postgres_query_result("upsert_subscription_watcher", start, &result);
     }
-    result
+    if result?.is_none() {
+        return Err(UpsertSubscriptionWatcherError::LimitReached);
+    }
+
+    Ok(())
 }
 
-pub struct SubscriberWithProject {
-    /// App domain that the subscription refers to
-    pub app_domain: String,
-    /// Authentication key used for authenticating topic JWTs and setting JWT aud field
-    pub authentication_public_key: String,
-    /// CAIP-10 account
-    pub account: AccountId, // TODO do we need to return this?
-    /// Symetric key used for notify topic. sha256 to get notify topic to manage
-    /// the subscription and call wc_notifySubscriptionUpdate and
-    /// wc_notifySubscriptionDelete
-    pub sym_key: String,
-    /// Array of notification types enabled for this subscription
-    pub scope: HashSet<Uuid>,
-    /// Unix timestamp of expiration
-    pub expiry: DateTime<Utc>,
-    /// Number of unread notifications
-    pub unread_notification_count: u64,
+#[derive(Debug, thiserror::Error)]
+pub enum SubscribeAndWatchError {
+    #[error(transparent)]
+    Scope(#[from] ScopeError),
+
+    #[error("Invalid account: {0}")]
+    InvalidAccount(#[from] Caip10Error),
+
+    #[error("Subscription watcher limit reached")]
+    WatcherLimitReached,
+
+    #[error("Subscription watcher sym_key in-use by another watcher")]
+    WatcherSymKeyConflict,
+
+    #[error("Subscriber topic {0} already in-use by another subscriber")]
+    SubscriberTopicCollision(Topic),
+
+    #[error("SQL error: {0}")]
+    Sqlx(#[from] sqlx::error::Error),
 }
 
-#[derive(FromRow)]
-struct SubscriberWithProjectResult {
-    pub app_domain: String,
-    pub authentication_public_key: String,
+impl From<UpsertSubscriberError> for SubscribeAndWatchError {
+    fn from(err: UpsertSubscriberError) -> Self {
+        match err {
+            UpsertSubscriberError::Scope(e) => Self::Scope(e),
+            UpsertSubscriberError::InvalidAccount(e) => Self::InvalidAccount(e),
+            UpsertSubscriberError::TopicCollision(topic) => Self::SubscriberTopicCollision(topic),
+            UpsertSubscriberError::Sqlx(e) => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<UpsertSubscriptionWatcherError> for SubscribeAndWatchError {
+    fn from(err: UpsertSubscriptionWatcherError) -> Self {
+        match err {
+            UpsertSubscriptionWatcherError::LimitReached => Self::WatcherLimitReached,
+            UpsertSubscriptionWatcherError::SymKeyConflict => Self::WatcherSymKeyConflict,
+            UpsertSubscriptionWatcherError::Sqlx(e) => Self::Sqlx(e),
+        }
+    }
+}
+
+/// Subscribes the account to the project and registers a subscription watcher for the same app in
+/// one transaction, so either both rows are written or neither is. Returns the subscriber ID.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(postgres, metrics))]
+pub async fn subscribe_and_watch(
+    project: Uuid,
+    account: AccountId,
+    scope: HashSet<Uuid>,
+    scope_policy: ScopePolicy,
+    notify_key: &[u8; 32],
+    notify_topic: Topic,
+    watcher_did_key: &str,
+    watcher_sym_key: &str,
+    watcher_expiry: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Uuid, SubscribeAndWatchError> {
+    validate_scope(&scope, scope_policy)?;
+    validate_account(&account)?;
+
+    let mut txn = postgres.begin().await?;
+    let subscriber = upsert_subscriber_impl(
+        project,
+        account.clone(),
+        scope,
+        scope_policy,
+        notify_key,
+        notify_topic,
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    upsert_subscription_watcher_impl(
+        account,
+        Some(project),
+        watcher_did_key,
+        watcher_sym_key,
+        watcher_expiry,
+        &mut txn,
+        metrics,
+    )
+    .await?;
+    txn.commit().await?;
+
+    Ok(subscriber.subscriber.id)
+}
+
+#[derive(Debug, FromRow)]
+pub struct SubscriptionWatcherQuery {
     #[sqlx(try_from = "String")]
     pub account: AccountId,
+    pub project: Option<Uuid>,
+    pub did_key: String,
     pub sym_key: String,
-    pub scope: Vec<String>,
     pub expiry: DateTime<Utc>,
-    pub unread_notification_count: i64,
 }
 
-impl From<SubscriberWithProjectResult> for SubscriberWithProject {
-    fn from(val: SubscriberWithProjectResult) -> Self {
-        SubscriberWithProject {
-            app_domain: val.app_domain,
-            authentication_public_key: val.authentication_public_key,
-            account: val.account,
-            sym_key: val.sym_key,
-            scope: parse_scopes_and_ignore_invalid(&val.scope),
-            expiry: val.expiry,
-            unread_notification_count: val
-                .unread_notification_count
-                .try_into()
-                .map_err(|e| {
-                    // This error shouldn't happen so not bothering with returning Result here
-                    // But if it does, this is a bug and apply use defensive programming
-                    error!("Error converting unread_notification_count from i64 to u64: {e}");
-                    e
-                })
-                .unwrap_or(0),
-        }
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscription_watchers_for_account_by_app_or_all_app(
+    account: &AccountId,
+    app_domain: &str,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriptionWatcherQuery>, sqlx::error::Error> {
+    let query = "
+        SELECT account, project, did_key, sym_key, expiry
+        FROM subscription_watcher
+        LEFT JOIN project ON project.id=subscription_watcher.project
+        WHERE expiry > now()
+              AND get_address_lower(account)=get_address_lower($1)
+              AND (project IS NULL OR project.app_domain=$2)
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriptionWatcherQuery>(query)
+        .bind(account.as_ref())
+        .bind(normalize_app_domain_for_lookup(app_domain))
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(
+            "get_subscription_watchers_for_account_by_app_or_all_app",
+            start,
+            &result,
+        );
     }
+    result
 }
 
-fn parse_scopes_and_ignore_invalid(scopes: &[String]) -> HashSet<Uuid> {
-    scopes
-        .iter()
-        .filter_map(|s| Uuid::parse_str(s).ok())
-        .collect()
+/// Same as `get_subscription_watchers_for_account_by_app_or_all_app()` but for callers that already
+/// have the project's `id`, so the project table isn't joined
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscription_watchers_for_account_and_project(
+    account: &AccountId,
+    project: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Vec<SubscriptionWatcherQuery>, sqlx::error::Error> {
+    let query = "
+        SELECT account, project, did_key, sym_key, expiry
+        FROM subscription_watcher
+        WHERE expiry > now()
+              AND get_address_lower(account)=get_address_lower($1)
+              AND (project IS NULL OR project=$2)
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriptionWatcherQuery>(query)
+        .bind(account.as_ref())
+        .bind(project)
+        .fetch_all(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(
+            "get_subscription_watchers_for_account_and_project",
+            start,
+            &result,
+        );
+    }
+    result
 }
 
+/// Returns `None` if the watcher doesn't exist or has expired
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscriptions_by_account_and_maybe_app(
-    account: AccountId,
-    app_domain: Option<&str>,
+pub async fn get_subscription_watcher_by_did_key(
+    did_key: &str,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<SubscriberWithProject>, sqlx::error::Error> {
-    let and_app = if app_domain.is_some() {
-        "AND project.app_domain=$2"
-    } else {
-        ""
-    };
-    let query = format!(
-        "
-        SELECT
-            app_domain,
-            project.authentication_public_key,
-            account,
-            sym_key,
-            array_remove(array_agg(subscriber_scope.name), NULL) AS scope,
-            expiry,
-            (
-                SELECT COUNT(*)
-                FROM subscriber_notification
-                WHERE
-                    subscriber=subscriber.id
-                    AND is_read=false
-            ) AS unread_notification_count
-        FROM subscriber
-        JOIN project ON project.id=subscriber.project
-        LEFT JOIN subscriber_scope ON subscriber_scope.subscriber=subscriber.id
-        WHERE
-            get_address_lower(account)=get_address_lower($1)
-            {and_app}
-        GROUP BY
-            subscriber.id,
-            app_domain,
-            project.authentication_public_key,
-            account,
-            sym_key,
-            expiry
-        "
-    );
-    let builder =
-        sqlx::query_as::<Postgres, SubscriberWithProjectResult>(&query).bind(account.as_ref());
-    let builder = if let Some(app_domain) = app_domain {
-        builder.bind(app_domain)
-    } else {
-        builder
-    };
+) -> Result<Option<SubscriptionWatcherQuery>, sqlx::error::Error> {
+    let query = "
+        SELECT account, project, did_key, sym_key, expiry
+        FROM subscription_watcher
+        WHERE did_key=$1
+              AND expiry > now()
+    ";
     let start = Instant::now();
-    let result = builder
-        .fetch_all(postgres)
-        .await
-        .map(|result| result.into_iter().map(Into::into).collect());
+    let result = sqlx::query_as::<Postgres, SubscriptionWatcherQuery>(query)
+        .bind(did_key)
+        .fetch_optional(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_subscriptions_by_account_and_maybe_app", start);
+        metrics.postgres_query_result("get_subscription_watcher_by_did_key", start, &result);
     }
+    result
+}
 
+/// Like `get_subscription_watcher_by_did_key()` but also returns expired watchers that haven't
+/// been cleaned up yet, for debugging why a watcher isn't receiving updates. Check `expiry` to
+/// tell whether the watcher is live.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscription_watcher_by_did_key_including_expired(
+    did_key: &str,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<Option<SubscriptionWatcherQuery>, sqlx::error::Error> {
+    let query = "
+        SELECT account, project, did_key, sym_key, expiry
+        FROM subscription_watcher
+        WHERE did_key=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriptionWatcherQuery>(query)
+        .bind(did_key)
+        .fetch_optional(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result(
+            "get_subscription_watcher_by_did_key_including_expired",
+            start,
+            &result,
+        );
+    }
     result
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum UpsertSubscriptionWatcherError {
-    #[error("Subscription watcher limit reached")]
-    LimitReached,
+/// Returns `false` if there was no watcher with this `did_key`
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscription_watcher(
+    did_key: &str,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<bool, sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscription_watcher
+        WHERE did_key=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(did_key)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscription_watcher", start, &result);
+    }
+    Ok(result?.rows_affected() > 0)
+}
 
-    #[error("SQL error: {0}")]
-    Sqlx(#[from] sqlx::error::Error),
+/// Deletes all of the account's watchers, expired or not, e.g. to erase the account. Matches the
+/// account case-insensitively on the address like the other account lookups. Returns the number
+/// of watchers deleted.
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_subscription_watchers_for_account(
+    account: AccountId,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<u64, sqlx::error::Error> {
+    let query = "
+        DELETE FROM subscription_watcher
+        WHERE get_address_lower(account)=get_address_lower($1)
+    ";
+    let start = Instant::now();
+    let result = sqlx::query::<Postgres>(query)
+        .bind(account.as_ref())
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("delete_subscription_watchers_for_account", start, &result);
+    }
+    Ok(result?.rows_affected())
 }
 
+/// Renews the watcher without resupplying its other fields. Returns false if there is no unexpired
+/// watcher for `did_key`.
 #[instrument(skip(postgres, metrics))]
-pub async fn upsert_subscription_watcher(
-    account: AccountId,
-    project: Option<Uuid>,
+pub async fn extend_subscription_watcher_expiry(
     did_key: &str,
-    sym_key: &str,
-    expiry: DateTime<Utc>,
+    new_expiry: DateTime<Utc>,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<(), UpsertSubscriptionWatcherError> {
+) -> Result<bool, sqlx::error::Error> {
     let query = "
-        INSERT INTO subscription_watcher (
-            account,
-            project,
-            did_key,
-            sym_key,
-            expiry
-        )
-        SELECT $1, $2, $3, $4, $5 WHERE (
-            SELECT COUNT(*)
-            FROM subscription_watcher
-            WHERE get_address_lower(account)=get_address_lower($1)
-                  AND project=$2
-        ) < $6
-        ON CONFLICT (did_key) DO UPDATE SET
-            updated_at=now(),
-            account=$1,
-            project=$2,
-            sym_key=$4,
-            expiry=$5
-        RETURNING *
+        UPDATE subscription_watcher
+        SET updated_at=now(),
+            expiry=$2
+        WHERE did_key=$1
+              AND expiry > now()
     ";
     let start = Instant::now();
-    let mut txn = postgres.begin().await?;
-    // https://stackoverflow.com/a/48730873
-    // Allow phantom reads; going above the watcher limit is not a big deal and handling
-    // serialization errors is not worth the effort
-    // sqlx::query::<Postgres>("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE")
-    //     .execute(&mut *txn)
-    //     .await?;
-    let result = sqlx::query_as::<Postgres, ()>(query)
-        .bind(account.as_ref())
-        .bind(project)
+    let result = sqlx::query::<Postgres>(query)
         .bind(did_key)
-        .bind(sym_key)
-        .bind(expiry)
-        .bind(SUBSCRIPTION_WATCHER_LIMIT)
-        .fetch_optional(&mut *txn)
-        .await?;
-    if result.is_none() {
-        return Err(UpsertSubscriptionWatcherError::LimitReached);
+        .bind(new_expiry)
+        .execute(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("extend_subscription_watcher_expiry", start, &result);
     }
-    txn.commit().await?;
+    Ok(result?.rows_affected() > 0)
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn delete_expired_subscription_watchers(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct DeleteResult {
+        count: i64,
+    }
+    let query = "
+        WITH deleted AS (
+            DELETE FROM subscription_watcher
+            WHERE expiry <= now()
+            RETURNING *
+        )
+        SELECT count(*) FROM deleted
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, DeleteResult>(query)
+        .fetch_one(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("upsert_subscription_watcher", start);
+        metrics.postgres_query_result("delete_expired_subscription_watchers", start, &result);
     }
 
-    Ok(())
+    Ok(result?.count)
 }
 
-#[derive(Debug, FromRow)]
-pub struct SubscriptionWatcherQuery {
-    #[sqlx(try_from = "String")]
-    pub account: AccountId,
-    pub project: Option<Uuid>,
-    pub did_key: String,
-    pub sym_key: String,
+/// Dry run of `delete_expired_subscription_watchers()`: the number of watchers it would delete
+#[instrument(skip(postgres, metrics))]
+pub async fn count_expired_subscription_watchers(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
+    let query = "
+        SELECT count(*)
+        FROM subscription_watcher
+        WHERE expiry <= now()
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("count_expired_subscription_watchers", start, &result);
+    }
+
+    Ok(result?.count)
 }
 
 #[instrument(skip(postgres, metrics))]
-pub async fn get_subscription_watchers_for_account_by_app_or_all_app(
-    account: &AccountId,
-    app_domain: &str,
+pub async fn count_active_subscription_watchers(
     postgres: &PgPool,
     metrics: Option<&Metrics>,
-) -> Result<Vec<SubscriptionWatcherQuery>, sqlx::error::Error> {
+) -> Result<i64, sqlx::error::Error> {
+    #[derive(Debug, FromRow)]
+    struct CountResult {
+        count: i64,
+    }
     let query = "
-        SELECT account, project, did_key, sym_key
+        SELECT count(*)
         FROM subscription_watcher
-        LEFT JOIN project ON project.id=subscription_watcher.project
         WHERE expiry > now()
-              AND get_address_lower(account)=get_address_lower($1)
-              AND (project IS NULL OR project.app_domain=$2)
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, SubscriptionWatcherQuery>(query)
-        .bind(account.as_ref())
-        .bind(app_domain)
-        .fetch_all(postgres)
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .fetch_one(postgres)
         .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query(
-            "get_subscription_watchers_for_account_by_app_or_all_app",
-            start,
-        );
+        metrics.postgres_query_result("count_active_subscription_watchers", start, &result);
     }
-    result
+
+    Ok(result?.count)
 }
 
+/// Count of live watchers for `account`, case-insensitive on the address like the other account lookups
 #[instrument(skip(postgres, metrics))]
-pub async fn delete_expired_subscription_watchers(
+pub async fn count_watchers_for_account(
+    account: &AccountId,
     postgres: &PgPool,
     metrics: Option<&Metrics>,
 ) -> Result<i64, sqlx::error::Error> {
     #[derive(Debug, FromRow)]
-    struct DeleteResult {
+    struct CountResult {
         count: i64,
     }
     let query = "
-        WITH deleted AS (
-            DELETE FROM subscription_watcher
-            WHERE expiry <= now()
-            RETURNING *
-        )
-        SELECT count(*) FROM deleted
+        SELECT count(*)
+        FROM subscription_watcher
+        WHERE get_address_lower(account)=get_address_lower($1)
+              AND expiry > now()
     ";
     let start = Instant::now();
-    let result = sqlx::query_as::<Postgres, DeleteResult>(query)
+    let result = sqlx::query_as::<Postgres, CountResult>(query)
+        .bind(account.as_ref())
         .fetch_one(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("delete_expired_subscription_watchers", start);
+        metrics.postgres_query_result("count_watchers_for_account", start, &result);
     }
 
-    Ok(result.count)
+    Ok(result?.count)
 }
 
 #[derive(Debug, FromRow, Clone, Serialize, Deserialize)]
@@ -992,10 +4357,11 @@ pub async fn get_notifications_for_subscriber(
     };
 
     let start = Instant::now();
-    let mut notifications = builder.fetch_all(postgres).await?;
+    let result = builder.fetch_all(postgres).await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_notifications_for_subscriber", start);
+        metrics.postgres_query_result("get_notifications_for_subscriber", start, &result);
     }
+    let mut notifications = result?;
 
     let has_more = notifications.len() > limit;
     notifications.truncate(limit);
@@ -1016,14 +4382,19 @@ pub async fn get_notifications_for_subscriber(
             after_clause = after_clause(Some(last_notification_id), "$2"),
         );
         let start = Instant::now();
-        let has_more_unread = sqlx::query_as::<Postgres, ()>(query)
+        let result = sqlx::query_as::<Postgres, ()>(query)
             .bind(subscriber)
             .bind(last_notification_id)
             .fetch_optional(postgres)
-            .await?;
+            .await;
         if let Some(metrics) = metrics {
-            metrics.postgres_query("get_notifications_for_subscriber_has_more_unread", start);
+            metrics.postgres_query_result(
+                "get_notifications_for_subscriber_has_more_unread",
+                start,
+                &result,
+            );
         }
+        let has_more_unread = result?;
         has_more_unread.is_some()
     } else {
         false
@@ -1060,14 +4431,14 @@ pub async fn get_welcome_notification(
         WHERE project=$1
     ";
     let start = Instant::now();
-    let welcome_notification = sqlx::query_as::<Postgres, WelcomeNotification>(query)
+    let result = sqlx::query_as::<Postgres, WelcomeNotification>(query)
         .bind(project)
         .fetch_optional(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_welcome_notification", start);
+        metrics.postgres_query_result("get_welcome_notification", start, &result);
     }
-    Ok(welcome_notification)
+    Ok(result?)
 }
 
 #[instrument(skip(postgres, metrics))]
@@ -1088,7 +4459,7 @@ pub async fn set_welcome_notification(
             url=EXCLUDED.url
     ";
     let start = Instant::now();
-    sqlx::query(query)
+    let result = sqlx::query(query)
         .bind(project)
         .bind(welcome_notification.enabled)
         .bind(welcome_notification.r#type)
@@ -1096,10 +4467,169 @@ pub async fn set_welcome_notification(
         .bind(welcome_notification.body)
         .bind(welcome_notification.url)
         .execute(postgres)
-        .await?;
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("set_welcome_notification", start, &result);
+    }
+    result?;
+    Ok(())
+}
+
+/// Counts a notification sent by the project in the window starting at `window_start` and returns
+/// the window's new count. The increment happens in the upsert, so concurrent sends are all
+/// counted.
+#[instrument(skip(postgres, metrics))]
+pub async fn increment_project_send_count(
+    project: Uuid,
+    window_start: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::Error> {
+    let query = "
+        INSERT INTO notification_rate_limit (project, window_start, count)
+        VALUES ($1, $2, 1)
+        ON CONFLICT (project, window_start) DO UPDATE SET
+            updated_at=now(),
+            count=notification_rate_limit.count + 1
+        RETURNING count
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, i64>(query)
+        .bind(project)
+        .bind(window_start)
+        .fetch_one(postgres)
+        .await;
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("increment_project_send_count", start, &result);
+    }
+    result
+}
+
+/// Number of notifications the project sent in the window starting at `window_start`. 0 if none.
+#[instrument(skip(postgres, metrics))]
+pub async fn get_project_send_count(
+    project: Uuid,
+    window_start: DateTime<Utc>,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<i64, sqlx::Error> {
+    let query = "
+        SELECT count
+        FROM notification_rate_limit
+        WHERE project=$1
+              AND window_start=$2
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_scalar::<Postgres, i64>(query)
+        .bind(project)
+        .bind(window_start)
+        .fetch_optional(postgres)
+        .await
+        .map(|count| count.unwrap_or(0));
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_project_send_count", start, &result);
+    }
+    result
+}
+
+/// Restrictions on delivering notifications to a subscriber on top of its scope. The default, also
+/// returned for subscribers that never set any, is no restrictions.
+#[derive(Debug, Default, PartialEq, Eq, Clone, FromRow)]
+pub struct SubscriberPreferences {
+    /// Start (UTC) of the daily window in which notifications are suppressed. Set together with
+    /// `quiet_hours_end`.
+    pub quiet_hours_start: Option<NaiveTime>,
+    /// End (UTC, exclusive) of the quiet hours. Before `quiet_hours_start` if they span midnight.
+    pub quiet_hours_end: Option<NaiveTime>,
+    /// Maximum number of notifications per day. None for no cap.
+    pub max_per_day: Option<i32>,
+}
+
+impl SubscriberPreferences {
+    pub fn is_quiet_at(&self, time: NaiveTime) -> bool {
+        match (self.quiet_hours_start, self.quiet_hours_end) {
+            (Some(start), Some(end)) if start <= end => start <= time && time < end,
+            (Some(start), Some(end)) => start <= time || time < end,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UpsertSubscriberPreferencesError {
+    #[error("Quiet hours start and end must be set together")]
+    IncompleteQuietHours,
+
+    #[error("Max per day must not be negative")]
+    NegativeMaxPerDay,
+
+    #[error(transparent)]
+    Sqlx(#[from] sqlx::error::Error),
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn get_subscriber_preferences(
+    subscriber: Uuid,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<SubscriberPreferences, sqlx::Error> {
+    let query = "
+        SELECT quiet_hours_start, quiet_hours_end, max_per_day
+        FROM subscriber_preferences
+        WHERE subscriber=$1
+    ";
+    let start = Instant::now();
+    let result = sqlx::query_as::<Postgres, SubscriberPreferences>(query)
+        .bind(subscriber)
+        .fetch_optional(postgres)
+        .await
+        .map(Option::unwrap_or_default);
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("get_subscriber_preferences", start, &result);
+    }
+    result
+}
+
+#[instrument(skip(postgres, metrics))]
+pub async fn upsert_subscriber_preferences(
+    subscriber: Uuid,
+    preferences: SubscriberPreferences,
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), UpsertSubscriberPreferencesError> {
+    if preferences.quiet_hours_start.is_some() != preferences.quiet_hours_end.is_some() {
+        return Err(UpsertSubscriberPreferencesError::IncompleteQuietHours);
+    }
+    if preferences.max_per_day.is_some_and(|max| max < 0) {
+        return Err(UpsertSubscriberPreferencesError::NegativeMaxPerDay);
+    }
+
+    let query = "
+        INSERT INTO subscriber_preferences (
+            subscriber,
+            quiet_hours_start,
+            quiet_hours_end,
+            max_per_day
+        )
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (subscriber) DO UPDATE SET
+            updated_at=now(),
+            quiet_hours_start=EXCLUDED.quiet_hours_start,
+            quiet_hours_end=EXCLUDED.quiet_hours_end,
+            max_per_day=EXCLUDED.max_per_day
+    ";
+    let start = Instant::now();
+    let result = sqlx::query(query)
+        .bind(subscriber)
+        .bind(preferences.quiet_hours_start)
+        .bind(preferences.quiet_hours_end)
+        .bind(preferences.max_per_day)
+        .execute(postgres)
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("set_welcome_notification", start);
+        metrics.postgres_query_result("upsert_subscriber_preferences", start, &result);
     }
+    result?;
     Ok(())
 }
 
@@ -1143,14 +4673,14 @@ pub async fn get_notification_link(
         WHERE subscriber_notification.id=$1
     ";
     let start = Instant::now();
-    let notification = sqlx::query_as::<Postgres, FollowNotificationLink>(query)
+    let result = sqlx::query_as::<Postgres, FollowNotificationLink>(query)
         .bind(subscriber_notification_id)
         .fetch_optional(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("get_notification_link", start);
+        metrics.postgres_query_result("get_notification_link", start, &result);
     }
-    Ok(notification)
+    Ok(result?)
 }
 
 #[derive(Debug, Clone, FromRow)]
@@ -1236,12 +4766,12 @@ pub async fn mark_notifications_as_read(
     };
 
     let start = Instant::now();
-    let results = builder.fetch_all(postgres).await?;
+    let result = builder.fetch_all(postgres).await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("mark_notifications_as_read", start);
+        metrics.postgres_query_result("mark_notifications_as_read", start, &result);
     }
 
-    Ok(results)
+    Ok(result?)
 }
 
 #[instrument(skip(postgres, metrics))]
@@ -1262,20 +4792,167 @@ pub async fn mark_all_notifications_as_read_for_project(
     ";
 
     let start = Instant::now();
-    let results = sqlx::query_as::<Postgres, MarkNotificationsAsReadResultRow>(query)
+    let result = sqlx::query_as::<Postgres, MarkNotificationsAsReadResultRow>(query)
         .bind(project)
         .fetch_all(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("mark_all_notifications_as_read_for_project", start);
+        metrics.postgres_query_result("mark_all_notifications_as_read_for_project", start, &result);
     }
 
-    Ok(results)
+    Ok(result?)
+}
+
+const CHECK_DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Verifies that a connection can be acquired from the pool and used. Fails with a `TimedOut` IO
+/// error if this takes longer than `CHECK_DATABASE_TIMEOUT`, so a hung connection can't hang a
+/// readiness probe.
+#[instrument(skip(postgres, metrics))]
+pub async fn check_database(
+    postgres: &PgPool,
+    metrics: Option<&Metrics>,
+) -> Result<(), sqlx::error::Error> {
+    let query = "SELECT 1";
+    let start = Instant::now();
+    let result = tokio::time::timeout(
+        CHECK_DATABASE_TIMEOUT,
+        sqlx::query::<Postgres>(query).execute(postgres),
+    )
+    .await;
+    let result = match result {
+        Ok(result) => result.map(|_| ()),
+        Err(_elapsed) => Err(sqlx::Error::Io(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "check_database timed out",
+        ))),
+    };
+    if let Some(metrics) = metrics {
+        metrics.postgres_query_result("check_database", start, &result);
+    }
+    result
 }
 
 #[cfg(test)]
 mod tests {
-    use {super::*, serde_json::json, validator::ValidateArgs};
+    use {
+        super::*,
+        serde_json::json,
+        std::sync::atomic::{AtomicU32, Ordering},
+        validator::ValidateArgs,
+    };
+
+    #[test]
+    fn normalize_app_domain_canonical_form() {
+        assert_eq!(normalize_app_domain("example.com").unwrap(), "example.com");
+        assert_eq!(normalize_app_domain("Example.COM").unwrap(), "example.com");
+        assert_eq!(normalize_app_domain("example.com.").unwrap(), "example.com");
+        assert_eq!(
+            normalize_app_domain("www.example.com").unwrap(),
+            "www.example.com"
+        );
+        assert_eq!(normalize_app_domain("com").unwrap(), "com");
+    }
+
+    #[test]
+    fn normalize_app_domain_invalid() {
+        assert!(matches!(
+            normalize_app_domain(""),
+            Err(AppDomainError::Invalid)
+        ));
+        assert!(matches!(
+            normalize_app_domain("."),
+            Err(AppDomainError::Invalid)
+        ));
+        assert!(matches!(
+            normalize_app_domain("example..com"),
+            Err(AppDomainError::Invalid)
+        ));
+        assert!(matches!(
+            normalize_app_domain("https://example.com"),
+            Err(AppDomainError::Invalid)
+        ));
+        assert!(matches!(
+            normalize_app_domain(" example.com"),
+            Err(AppDomainError::Invalid)
+        ));
+        assert!(matches!(
+            normalize_app_domain(&format!("{}.com", "a".repeat(250))),
+            Err(AppDomainError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn escape_like_pattern_wildcards() {
+        assert_eq!(escape_like_pattern("example.com"), "example.com");
+        assert_eq!(escape_like_pattern("my_app%"), "my\\_app\\%");
+        assert_eq!(escape_like_pattern("a\\b"), "a\\\\b");
+    }
+
+    #[test]
+    fn subscriber_preferences_quiet_hours() {
+        let time = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        assert!(!SubscriberPreferences::default().is_quiet_at(time(3)));
+
+        let daytime = SubscriberPreferences {
+            quiet_hours_start: Some(time(9)),
+            quiet_hours_end: Some(time(17)),
+            max_per_day: None,
+        };
+        assert!(daytime.is_quiet_at(time(9)));
+        assert!(daytime.is_quiet_at(time(12)));
+        assert!(!daytime.is_quiet_at(time(17)));
+        assert!(!daytime.is_quiet_at(time(3)));
+
+        let overnight = SubscriberPreferences {
+            quiet_hours_start: Some(time(22)),
+            quiet_hours_end: Some(time(7)),
+            max_per_day: None,
+        };
+        assert!(overnight.is_quiet_at(time(23)));
+        assert!(overnight.is_quiet_at(time(3)));
+        assert!(!overnight.is_quiet_at(time(7)));
+        assert!(!overnight.is_quiet_at(time(12)));
+    }
+
+    #[tokio::test]
+    async fn with_retry_retries_transient_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(2, Duration::ZERO, || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(sqlx::Error::PoolTimedOut)
+            } else {
+                Ok(())
+            }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn with_retry_gives_up() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(2, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(sqlx::Error::PoolTimedOut)
+        })
+        .await;
+        assert!(matches!(result, Err(sqlx::Error::PoolTimedOut)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn with_retry_does_not_retry_logical_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = with_retry(2, Duration::ZERO, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(sqlx::Error::RowNotFound)
+        })
+        .await;
+        assert!(matches!(result, Err(sqlx::Error::RowNotFound)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
 
     #[test]
     fn mark_notification_as_read_params_ids() {
@@ -20,6 +20,11 @@ pub mod erc55;
     ::derive_more::AsRef,
 )]
 #[doc = "A CAIP-10 account ID."]
+///
+/// The string is stored exactly as validated, without trimming or case normalization, so
+/// `AccountId::try_from(account.to_string())` always returns an equal `AccountId`. eip155 addresses
+/// must be ERC-55 checksummed so there is only one accepted spelling of each, but queries still
+/// compare with `get_address_lower()` so lookups don't depend on it.
 #[as_ref(forward)]
 pub struct AccountId(Arc<str>);
 
@@ -89,7 +94,7 @@ impl AccountId {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use super::{eip155::test_utils, *};
 
     #[test]
     fn to_did_pkh() {
@@ -98,6 +103,38 @@ mod tests {
         assert_eq!(account_id.to_did_pkh(), format!("did:pkh:{address}"));
     }
 
+    #[test]
+    fn round_trip() {
+        let address = "eip155:1:0x9AfEaC202C837df470b5A145e0EfD6a574B21029";
+        let account_id = AccountId::try_from(address).unwrap();
+        assert_eq!(account_id.as_ref(), address);
+        assert_eq!(account_id.to_string(), address);
+        assert_eq!(
+            AccountId::try_from(account_id.as_ref().to_string()).unwrap(),
+            account_id
+        );
+
+        for _ in 0..100 {
+            let (_, account_id) = test_utils::generate_account();
+            assert_eq!(
+                AccountId::try_from(account_id.as_ref().to_string()).unwrap(),
+                account_id
+            );
+            assert_eq!(
+                AccountId::try_from(account_id.to_string()).unwrap(),
+                account_id
+            );
+        }
+    }
+
+    #[test]
+    fn not_normalized() {
+        let address = " eip155:1:0x9AfEaC202C837df470b5A145e0EfD6a574B21029";
+        assert!(AccountId::try_from(address).is_err());
+        let address = "eip155:1:0x9afeac202c837df470b5a145e0efd6a574b21029";
+        assert!(AccountId::try_from(address).is_err());
+    }
+
     #[test]
     fn from_did_pkh() {
         let address = "eip155:1:0x9AfEaC202C837df470b5A145e0EfD6a574B21029";
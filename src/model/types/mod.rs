@@ -5,12 +5,13 @@ use {
     },
     chrono::{DateTime, Utc},
     relay_rpc::{
-        auth::ed25519_dalek::VerifyingKey,
+        auth::ed25519_dalek::{SigningKey, VerifyingKey},
         domain::{DecodedClientId, ProjectId, Topic},
     },
     sqlx::FromRow,
     thiserror::Error,
     uuid::Uuid,
+    x25519_dalek::StaticSecret,
 };
 
 // See /migrations/ERD.md
@@ -18,6 +19,9 @@ use {
 mod account_id;
 pub use account_id::*;
 
+mod sym_key;
+pub use sym_key::*;
+
 #[derive(Debug, FromRow)]
 pub struct Project {
     pub id: Uuid,
@@ -30,6 +34,25 @@ pub struct Project {
     pub authentication_private_key: String,
     pub subscribe_public_key: String,
     pub subscribe_private_key: String,
+    #[sqlx(rename = "inserted_at")]
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// `Project` without the private keys, for code paths that don't sign or decrypt anything
+#[derive(Debug, FromRow)]
+pub struct ProjectPublic {
+    pub id: Uuid,
+    #[sqlx(try_from = "String")]
+    pub project_id: ProjectId,
+    pub app_domain: String,
+    #[sqlx(try_from = "String")]
+    pub topic: Topic,
+    pub authentication_public_key: String,
+    pub subscribe_public_key: String,
+    #[sqlx(rename = "inserted_at")]
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Error)]
@@ -49,6 +72,45 @@ impl Project {
             &self.authentication_public_key,
         )?)?))
     }
+
+    pub fn authentication_key_fingerprint(&self) -> Result<String, DecodeKeyError> {
+        authentication_key_fingerprint(&self.authentication_public_key)
+    }
+
+    pub fn authentication_key(&self) -> Result<SigningKey, DecodeKeyError> {
+        Ok(SigningKey::from_bytes(&decode_key(
+            &self.authentication_private_key,
+        )?))
+    }
+
+    pub fn subscribe_key(&self) -> Result<StaticSecret, DecodeKeyError> {
+        Ok(StaticSecret::from(decode_key(&self.subscribe_private_key)?))
+    }
+
+    pub fn keys(&self) -> Result<ProjectKeys, DecodeKeyError> {
+        Ok(ProjectKeys {
+            authentication_key: self.authentication_key()?,
+            subscribe_key: self.subscribe_key()?,
+        })
+    }
+}
+
+/// `Project`'s private keys, decoded
+pub struct ProjectKeys {
+    pub authentication_key: SigningKey,
+    pub subscribe_key: StaticSecret,
+}
+
+const AUTHENTICATION_KEY_FINGERPRINT_LENGTH: usize = 16;
+
+/// Short sha256 fingerprint of the decoded key bytes, so different hex encodings of the same key
+/// (e.g. upper vs lower case) have the same fingerprint
+pub fn authentication_key_fingerprint(
+    authentication_public_key: &str,
+) -> Result<String, DecodeKeyError> {
+    let mut fingerprint = sha256::digest(decode_key(authentication_public_key)?.as_slice());
+    fingerprint.truncate(AUTHENTICATION_KEY_FINGERPRINT_LENGTH);
+    Ok(fingerprint)
 }
 
 #[derive(Debug, FromRow)]
@@ -58,7 +120,8 @@ pub struct Subscriber {
     /// CAIP-10 account
     #[sqlx(try_from = "String")]
     pub account: AccountId,
-    pub sym_key: String,
+    #[sqlx(try_from = "String")]
+    pub sym_key: SymKey,
     #[sqlx(try_from = "String")]
     pub topic: Topic,
     pub expiry: DateTime<Utc>,
@@ -73,3 +136,24 @@ pub struct SubscriptionWatcher {
     pub sym_key: String,
     pub expiry: DateTime<Utc>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authentication_key_fingerprint_stable_across_encodings() {
+        let key = hex::encode(rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng()));
+        let fingerprint = authentication_key_fingerprint(&key).unwrap();
+        assert_eq!(fingerprint.len(), AUTHENTICATION_KEY_FINGERPRINT_LENGTH);
+        assert_eq!(
+            authentication_key_fingerprint(&key.to_ascii_uppercase()).unwrap(),
+            fingerprint
+        );
+    }
+
+    #[test]
+    fn authentication_key_fingerprint_invalid_key() {
+        assert!(authentication_key_fingerprint("not hex").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use {
+    crate::rpc::{decode_key, DecodeKeyError},
+    std::fmt,
+};
+
+/// 32-byte symmetric key of a subscriber. Stored hex-encoded in the database; encoding and decoding
+/// only happen here so raw and hex keys can't be mixed up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymKey([u8; 32]);
+
+impl SymKey {
+    pub fn from_hex(s: &str) -> Result<Self, DecodeKeyError> {
+        decode_key(s).map(Self)
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for SymKey {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl TryFrom<String> for SymKey {
+    type Error = DecodeKeyError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::from_hex(&s)
+    }
+}
+
+impl fmt::Display for SymKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+        let key = SymKey::from(bytes);
+        assert_eq!(key.to_hex(), hex::encode(bytes));
+        assert_eq!(key.to_string(), key.to_hex());
+        assert_eq!(SymKey::from_hex(&key.to_hex()).unwrap(), key);
+        assert_eq!(key.as_bytes(), &bytes);
+    }
+
+    #[test]
+    fn from_hex_invalid() {
+        assert!(SymKey::from_hex("not hex").is_err());
+        assert!(SymKey::from_hex(&hex::encode([0u8; 16])).is_err());
+    }
+}
@@ -9,7 +9,10 @@ use {
             IdentityVerificationInternalError, JwtError, SignJwtError,
         },
         error::NotifyServerError,
-        model::types::GetAuthenticationClientIdError,
+        model::{
+            helpers::ScopeError,
+            types::{caip10::Caip10Error, GetAuthenticationClientIdError},
+        },
         rate_limit::RateLimitExceeded,
         rpc::{DecodeKeyError, DeriveKeyError, JsonRpcError},
         types::EnvelopeParseError,
@@ -29,6 +32,9 @@ pub enum RelayMessageClientError {
     #[error("Subscription watcher limit reached")]
     SubscriptionWatcherLimitReached,
 
+    #[error("Subscription watcher sym_key in-use by another watcher")]
+    SubscriptionWatcherSymKeyConflict,
+
     #[error("Received 4008 on unrecognized topic: {0}")]
     WrongNotifyUpdateTopic(Topic),
 
@@ -79,6 +85,12 @@ pub enum RelayMessageClientError {
 
     #[error("Message received on topic, but the key associated with that topic does not hash to the topic")]
     TopicDoesNotMatchKey,
+
+    #[error("Invalid scope: {0}")]
+    InvalidScope(ScopeError),
+
+    #[error("Invalid account: {0}")]
+    InvalidAccount(Caip10Error),
 }
 
 #[derive(Debug, thiserror::Error)]
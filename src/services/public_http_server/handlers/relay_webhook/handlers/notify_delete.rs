@@ -16,10 +16,7 @@ use {
         publish_relay_message::publish_relay_message,
         rate_limit::{self, Clock, RateLimitError},
         registry::storage::redis::Redis,
-        rpc::{
-            decode_key, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError, NotifyDelete,
-            ResponseAuth,
-        },
+        rpc::{JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError, NotifyDelete, ResponseAuth},
         services::public_http_server::handlers::relay_webhook::{
             error::{RelayMessageClientError, RelayMessageError, RelayMessageServerError},
             handlers::{
@@ -41,7 +38,6 @@ use {
     base64::Engine,
     chrono::Utc,
     relay_rpc::{
-        auth::ed25519_dalek::SigningKey,
         domain::{DecodedClientId, Topic},
         rpc::Publish,
     },
@@ -80,7 +76,7 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
     )
     .map_err(RelayMessageClientError::EnvelopeParse)?;
 
-    let sym_key = decode_key(&subscriber.sym_key).map_err(RelayMessageServerError::DecodeKey)?;
+    let sym_key = *subscriber.sym_key.as_bytes();
     if msg.topic != topic_from_key(&sym_key) {
         Err(RelayMessageClientError::TopicDoesNotMatchKey)?;
     }
@@ -221,10 +217,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
         };
         let response_auth = sign_jwt(
             response_message,
-            &SigningKey::from_bytes(
-                &decode_key(&project.authentication_private_key)
-                    .map_err(RelayMessageServerError::DecodeKey)?,
-            ),
+            &project
+                .authentication_key()
+                .map_err(RelayMessageServerError::DecodeKey)?,
         )
         .map_err(RelayMessageServerError::SignJwt)?;
 
@@ -34,7 +34,6 @@ use {
     base64::Engine,
     chrono::Utc,
     relay_rpc::{
-        auth::ed25519_dalek::SigningKey,
         domain::{DecodedClientId, Topic},
         rpc::{msg_id::get_message_id, Publish},
     },
@@ -71,7 +70,7 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
     )
     .map_err(RelayMessageClientError::EnvelopeParse)?;
 
-    let sym_key = decode_key(&subscriber.sym_key).map_err(RelayMessageServerError::DecodeKey)?;
+    let sym_key = *subscriber.sym_key.as_bytes();
     if msg.topic != topic_from_key(&sym_key) {
         Err(RelayMessageClientError::TopicDoesNotMatchKey)?;
     }
@@ -196,10 +195,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
         };
         let auth = sign_jwt(
             response_message,
-            &SigningKey::from_bytes(
-                &decode_key(&project.authentication_private_key)
-                    .map_err(RelayMessageServerError::DecodeKey)?,
-            ),
+            &project
+                .authentication_key()
+                .map_err(RelayMessageServerError::DecodeKey)?,
         )
         .map_err(RelayMessageServerError::SignJwt)?;
         Ok(AuthMessage { auth })
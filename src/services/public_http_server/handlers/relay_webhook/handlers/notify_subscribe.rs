@@ -6,10 +6,11 @@ use {
             DidWeb, NotifyServerSubscription, SharedClaims, SubscriptionRequestAuth,
             SubscriptionResponseAuth,
         },
+        error::NotifyServerError,
         model::{
             helpers::{
                 get_project_by_topic, get_welcome_notification, upsert_subscriber,
-                SubscriptionWatcherQuery,
+                SubscriptionWatcherQuery, UpsertSubscriberError,
             },
             types::Project,
         },
@@ -17,8 +18,8 @@ use {
         rate_limit::{self, Clock, RateLimitError},
         registry::storage::redis::Redis,
         rpc::{
-            decode_key, derive_key, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError,
-            NotifySubscribe, ResponseAuth,
+            derive_key, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError, NotifySubscribe,
+            ResponseAuth,
         },
         services::{
             public_http_server::handlers::relay_webhook::{
@@ -44,7 +45,6 @@ use {
     base64::Engine,
     chrono::Utc,
     relay_rpc::{
-        auth::ed25519_dalek::SigningKey,
         domain::{DecodedClientId, Topic},
         rpc::Publish,
     },
@@ -90,9 +90,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
         notify_subscribe_client_rate_limit(redis, &client_public_key, &state.clock).await?;
     }
 
-    let server_public_key = x25519_dalek::StaticSecret::from(
-        decode_key(&project.subscribe_private_key).map_err(RelayMessageServerError::DecodeKey)?,
-    );
+    let server_public_key = project
+        .subscribe_key()
+        .map_err(RelayMessageServerError::DecodeKey)?;
 
     let sym_key = derive_key(&client_public_key, &server_public_key)
         .map_err(RelayMessageServerError::DeriveKey)?;
@@ -196,7 +196,22 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
                 state.metrics.as_ref(),
             )
             .await
-            .map_err(|e| RelayMessageServerError::NotifyServer(e.into()))? // TODO change to client error?
+            .map_err(|e| match e {
+                UpsertSubscriberError::Scope(e) => {
+                    RelayMessageError::Client(RelayMessageClientError::InvalidScope(e))
+                }
+                UpsertSubscriberError::InvalidAccount(e) => {
+                    RelayMessageError::Client(RelayMessageClientError::InvalidAccount(e))
+                }
+                UpsertSubscriberError::TopicCollision(topic) => {
+                    RelayMessageError::Server(RelayMessageServerError::NotifyServer(
+                        NotifyServerError::SubscriberTopicCollision(topic),
+                    ))
+                }
+                UpsertSubscriberError::Sqlx(e) => RelayMessageError::Server(
+                    RelayMessageServerError::NotifyServer(NotifyServerError::Sqlx(e)),
+                ),
+            })?
         };
         info!("Timing: Finished upserting subscriber");
 
@@ -311,10 +326,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
         };
         let response_auth = sign_jwt(
             response_message,
-            &SigningKey::from_bytes(
-                &decode_key(&project.authentication_private_key)
-                    .map_err(RelayMessageServerError::DecodeKey)?,
-            ),
+            &project
+                .authentication_key()
+                .map_err(RelayMessageServerError::DecodeKey)?,
         )
         .map_err(RelayMessageServerError::SignJwt)?;
         Ok((ResponseAuth { response_auth }, watchers_with_subscriptions))
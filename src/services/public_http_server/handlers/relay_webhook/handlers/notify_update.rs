@@ -7,20 +7,18 @@ use {
             DidWeb, NotifyServerSubscription, SharedClaims, SubscriptionUpdateRequestAuth,
             SubscriptionUpdateResponseAuth,
         },
+        error::NotifyServerError,
         model::{
             helpers::{
                 get_project_by_id, get_subscriber_by_topic, update_subscriber, SubscriberWithScope,
-                SubscriptionWatcherQuery,
+                SubscriptionWatcherQuery, UpdateSubscriberError,
             },
             types::Project,
         },
         publish_relay_message::publish_relay_message,
         rate_limit::{self, Clock, RateLimitError},
         registry::storage::redis::Redis,
-        rpc::{
-            decode_key, JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError, NotifyUpdate,
-            ResponseAuth,
-        },
+        rpc::{JsonRpcRequest, JsonRpcResponse, JsonRpcResponseError, NotifyUpdate, ResponseAuth},
         services::public_http_server::handlers::relay_webhook::{
             error::{RelayMessageClientError, RelayMessageError, RelayMessageServerError},
             handlers::{
@@ -39,7 +37,6 @@ use {
     base64::Engine,
     chrono::Utc,
     relay_rpc::{
-        auth::ed25519_dalek::SigningKey,
         domain::{DecodedClientId, Topic},
         rpc::Publish,
     },
@@ -78,7 +75,7 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
     )
     .map_err(RelayMessageClientError::EnvelopeParse)?;
 
-    let sym_key = decode_key(&subscriber.sym_key).map_err(RelayMessageServerError::DecodeKey)?;
+    let sym_key = *subscriber.sym_key.as_bytes();
     if msg.topic != topic_from_key(&sym_key) {
         Err(RelayMessageClientError::TopicDoesNotMatchKey)?;
     }
@@ -164,7 +161,14 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
             state.metrics.as_ref(),
         )
         .await
-        .map_err(|e| RelayMessageServerError::NotifyServer(e.into()))?; // TODO change to client error?
+        .map_err(|e| match e {
+            UpdateSubscriberError::Scope(e) => {
+                RelayMessageError::Client(RelayMessageClientError::InvalidScope(e))
+            }
+            UpdateSubscriberError::Sqlx(e) => RelayMessageError::Server(
+                RelayMessageServerError::NotifyServer(NotifyServerError::Sqlx(e)),
+            ),
+        })?;
 
         // TODO do in same transaction as update_subscriber()
         // state
@@ -216,10 +220,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
         };
         let response_auth = sign_jwt(
             response_auth,
-            &SigningKey::from_bytes(
-                &decode_key(&project.authentication_private_key)
-                    .map_err(RelayMessageServerError::DecodeKey)?,
-            ),
+            &project
+                .authentication_key()
+                .map_err(RelayMessageServerError::DecodeKey)?,
         )
         .map_err(RelayMessageServerError::SignJwt)?;
 
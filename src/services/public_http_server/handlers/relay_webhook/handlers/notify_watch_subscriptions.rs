@@ -182,6 +182,9 @@ pub async fn handle(msg: RelayIncomingMessage, state: &AppState) -> Result<(), R
             UpsertSubscriptionWatcherError::LimitReached => {
                 RelayMessageError::Client(RelayMessageClientError::SubscriptionWatcherLimitReached)
             }
+            UpsertSubscriptionWatcherError::SymKeyConflict => RelayMessageError::Client(
+                RelayMessageClientError::SubscriptionWatcherSymKeyConflict,
+            ),
             UpsertSubscriptionWatcherError::Sqlx(e) => RelayMessageError::Server(
                 RelayMessageServerError::NotifyServer(NotifyServerError::Sqlx(e)),
             ),
@@ -276,11 +279,17 @@ pub async fn collect_subscriptions(
     info!("Called collect_subscriptions");
 
     let subscriptions = if let Some(app_domain) = app_domain {
-        get_subscriptions_by_account_and_maybe_app(account, Some(app_domain), postgres, metrics)
-            .await
-            .map_err(CollectSubscriptionsError::Sqlx)?
+        get_subscriptions_by_account_and_maybe_app(
+            account,
+            Some(app_domain),
+            false,
+            postgres,
+            metrics,
+        )
+        .await
+        .map_err(CollectSubscriptionsError::Sqlx)?
     } else {
-        get_subscriptions_by_account_and_maybe_app(account, None, postgres, metrics)
+        get_subscriptions_by_account_and_maybe_app(account, None, false, postgres, metrics)
             .await
             .map_err(CollectSubscriptionsError::Sqlx)?
     };
@@ -298,7 +307,7 @@ pub async fn collect_subscriptions(
                             &sub.authentication_public_key,
                         )?)
                         .to_did_key(),
-                        sym_key: sub.sym_key,
+                        sym_key: sub.sym_key.to_hex(),
                         account: sub.account,
                         scope: sub.scope,
                         expiry: sub.expiry.timestamp() as u64,
@@ -1,7 +1,10 @@
 use {
     crate::{
         error::NotifyServerError,
-        model::helpers::upsert_project,
+        model::{
+            helpers::{upsert_project, UpsertProjectError},
+            types::authentication_key_fingerprint,
+        },
         publish_relay_message::subscribe_relay_topic,
         rate_limit::{self, Clock, RateLimitError},
         registry::{extractor::AuthedProjectId, storage::redis::Redis},
@@ -37,6 +40,8 @@ pub struct SubscribeTopicRequestBody {
 pub struct SubscribeTopicResponseBody {
     pub authentication_key: String,
     pub subscribe_key: String,
+    /// Fingerprint of `authentication_key` that clients can pin to detect unexpected rotations
+    pub authentication_key_fingerprint: String,
 }
 
 #[instrument(name = "notify_v1", skip(state, subscribe_topic_data))]
@@ -91,22 +96,36 @@ pub async fn handler(
     )
     .await
     .map_err(|e| match e {
-        sqlx::Error::Database(e)
+        UpsertProjectError::Sqlx(sqlx::Error::Database(e))
             if e.is_unique_violation() && e.message().contains("project_app_domain_key") =>
         {
             NotifyServerError::AppDomainInUseByAnotherProject
         }
-        other => other.into(),
+        UpsertProjectError::InvalidAppDomain(e) => {
+            NotifyServerError::UnprocessableEntity(e.to_string())
+        }
+        UpsertProjectError::Sqlx(e) => e.into(),
+        UpsertProjectError::TopicCollision(topic) => {
+            NotifyServerError::ProjectTopicCollision(topic)
+        }
     })?;
 
+    if project.inserted {
+        info!("Created new project");
+    }
+    let project = project.keys;
+
     let topic = project.topic.into();
     info!("Subscribing to project topic: {topic}");
     subscribe_relay_topic(&state.relay_client, &topic, state.metrics.as_ref()).await?;
 
     info!("Successfully subscribed to project topic: {topic}");
+    let authentication_key_fingerprint =
+        authentication_key_fingerprint(&project.authentication_public_key)?;
     Ok(Json(SubscribeTopicResponseBody {
         authentication_key: project.authentication_public_key,
         subscribe_key: project.subscribe_public_key,
+        authentication_key_fingerprint,
     })
     .into_response())
 }
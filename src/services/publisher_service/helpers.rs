@@ -49,10 +49,11 @@ pub async fn upsert_notification(
         .bind(notification.icon)
         .bind(notification.url)
         .fetch_one(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("upsert_notification", start);
+        metrics.postgres_query_result("upsert_notification", start, &result);
     }
+    let result = result?;
     Ok(NotificationWithId {
         id: result.id,
         notification: Notification {
@@ -77,15 +78,16 @@ pub async fn upsert_subscriber_notifications(
         ON CONFLICT (notification, subscriber) DO NOTHING
     ";
     let start = Instant::now();
-    sqlx::query(query)
+    let result = sqlx::query(query)
         .bind(notification)
         .bind(subscribers)
         .bind(SubscriberNotificationStatus::Queued.to_string())
         .execute(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("upsert_subscriber_notifications", start);
+        metrics.postgres_query_result("upsert_subscriber_notifications", start, &result);
     }
+    result?;
     Ok(())
 }
 
@@ -142,12 +144,17 @@ pub async fn pick_subscriber_notification_for_processing(
         RETURNING picked.id
     ";
     let start = Instant::now();
-    let picked = sqlx::query_as::<Postgres, Picked>(query)
+    let result = sqlx::query_as::<Postgres, Picked>(query)
         .fetch_optional(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("pick_subscriber_notification_for_processing", start);
+        metrics.postgres_query_result(
+            "pick_subscriber_notification_for_processing",
+            start,
+            &result,
+        );
     }
+    let picked = result?;
 
     let notification = if let Some(picked) = picked {
         let query = "
@@ -177,14 +184,19 @@ pub async fn pick_subscriber_notification_for_processing(
             WHERE subscriber_notification.id=$1
         ";
         let start = Instant::now();
-        let notification = sqlx::query_as::<Postgres, NotificationToProcess>(query)
+        let result = sqlx::query_as::<Postgres, NotificationToProcess>(query)
             .bind(picked.id)
             // If somehow the second query returned no results, it's probably a race condition and the row got deleted. We should just ignore it.
             .fetch_optional(postgres)
-            .await?;
+            .await;
         if let Some(metrics) = metrics {
-            metrics.postgres_query("select_subscriber_notification_for_processing", start);
+            metrics.postgres_query_result(
+                "select_subscriber_notification_for_processing",
+                start,
+                &result,
+            );
         }
+        let notification = result?;
 
         if notification.is_none() {
             warn!("Race condition at select_subscriber_notification_for_processing?");
@@ -212,14 +224,15 @@ pub async fn update_message_processing_status<'e>(
         WHERE id=$2;
     ";
     let start = Instant::now();
-    sqlx::query::<Postgres>(mark_message_as_processed)
+    let result = sqlx::query::<Postgres>(mark_message_as_processed)
         .bind(status.to_string())
         .bind(notification)
         .execute(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("update_message_processing_status", start);
+        metrics.postgres_query_result("update_message_processing_status", start, &result);
     }
+    result?;
 
     if let Some(metrics) = metrics {
         update_metrics_on_message_status_change(metrics, status).await;
@@ -250,10 +263,11 @@ pub async fn get_publishing_queue_stats(
         (SELECT COUNT(*) FROM subscriber_notification WHERE status = 'processing') AS processing
     ";
     let start = Instant::now();
-    let notification = sqlx::query_as::<Postgres, PublishingQueueStats>(query)
+    let result = sqlx::query_as::<Postgres, PublishingQueueStats>(query)
         .fetch_one(postgres)
-        .await?;
-    metrics.postgres_query("get_publishing_queue_stats", start);
+        .await;
+    metrics.postgres_query_result("get_publishing_queue_stats", start, &result);
+    let notification = result?;
 
     Ok(notification)
 }
@@ -291,13 +305,14 @@ pub async fn dead_letters_check(
         AND updated_at < $1
     ";
     let start = Instant::now();
-    sqlx::query::<Postgres>(update_status_query)
+    let result = sqlx::query::<Postgres>(update_status_query)
         .bind(Utc::now() - threshold)
         .execute(postgres)
-        .await?;
+        .await;
     if let Some(metrics) = metrics {
-        metrics.postgres_query("dead_letters_check", start);
+        metrics.postgres_query_result("dead_letters_check", start, &result);
     }
+    result?;
     Ok(())
 }
 
@@ -6,6 +6,7 @@ use {
         auth::{DidWeb, SignJwtError},
         error::NotifyServerError,
         metrics::Metrics,
+        model::{helpers::touch_subscriber_notified, types::SymKey},
         notify_message::{sign_message, JwtNotification, ProjectSigningDetails},
         publish_relay_message::publish_relay_message,
         rpc::{decode_key, DecodeKeyError, JsonRpcRequest, NotifyMessageAuth},
@@ -217,6 +218,7 @@ async fn process_queued_messages(
             info!("Got a notification with id: {}", notification_id);
 
             let notification_created_at = notification.notification_created_at;
+            let subscriber = notification.subscriber;
             let process_result = timeout(
                 PUBLISHING_TIMEOUT,
                 process_notification(
@@ -248,6 +250,9 @@ async fn process_queued_messages(
                         metrics,
                     )
                     .await?;
+                    if let Err(e) = touch_subscriber_notified(subscriber, postgres, metrics).await {
+                        warn!("Error updating subscriber last_notified_at: {e:?}");
+                    }
                 }
                 Ok(Err(e)) => {
                     warn!("Error on `process_notification`: {:?}", e);
@@ -336,15 +341,15 @@ async fn process_notification(
         },
     );
 
-    let sym_key = decode_key(&notification.subscriber_sym_key)
+    let sym_key = SymKey::from_hex(&notification.subscriber_sym_key)
         .map_err(ProcessNotificationError::DecodeKey)?;
     let envelope = Envelope::<EnvelopeType0>::new(
-        &sym_key,
+        sym_key.as_bytes(),
         serde_json::to_vec(&message).map_err(ProcessNotificationError::EnvelopeSerialization)?,
     )
     .map_err(ProcessNotificationError::EnvelopeEncryption)?;
     let base64_notification = base64::engine::general_purpose::STANDARD.encode(envelope.to_bytes());
-    let topic = topic_from_key(&sym_key);
+    let topic = topic_from_key(sym_key.as_bytes());
 
     let publish = Publish {
         topic,
@@ -1,8 +1,6 @@
 use {
     crate::{
-        error::NotifyServerError,
-        metrics::Metrics,
-        model::helpers::{get_project_topics, get_subscriber_topics},
+        error::NotifyServerError, metrics::Metrics, model::helpers::get_all_topics,
         publish_relay_message::batch_subscribe_relay_topics,
     },
     futures_util::StreamExt,
@@ -27,20 +25,14 @@ pub async fn run(
     info!("Resubscribing to all topics");
     let start = Instant::now();
 
-    let subscriber_topics = get_subscriber_topics(postgres, metrics).await?;
-    let subscriber_topics_count = subscriber_topics.len();
-    info!("subscriber_topics_count: {subscriber_topics_count}");
-
-    let project_topics = get_project_topics(postgres, metrics).await?;
-    let project_topics_count = project_topics.len();
-    info!("project_topics_count: {project_topics_count}");
+    // Project and subscriber topics, deduplicated by the database
+    let topics = get_all_topics(postgres, metrics).await?;
 
     // TODO: These need to be paginated and streamed from the database directly
     // instead of collecting them to a single giant vec.
     let topics = [key_agreement_topic]
         .into_iter()
-        .chain(subscriber_topics.into_iter())
-        .chain(project_topics.into_iter())
+        .chain(topics.into_iter())
         .collect::<Vec<_>>();
     let topics_count = topics.len();
     info!("topics_count: {topics_count}");
@@ -5,8 +5,8 @@ use {
         relay_api::{decode_message, decode_response_message},
         RelayClient, RELAY_MESSAGE_DELIVERY_TIMEOUT,
     },
-    chrono::{DateTime, Duration, Utc},
-    futures::future::BoxFuture,
+    chrono::{DateTime, Duration, DurationRound, NaiveTime, Utc},
+    futures::{future::BoxFuture, TryStreamExt},
     futures_util::StreamExt,
     hyper::StatusCode,
     itertools::Itertools,
@@ -28,20 +28,67 @@ use {
         config::Configuration,
         model::{
             helpers::{
-                get_notifications_for_subscriber, get_project_by_app_domain,
-                get_project_by_project_id, get_project_by_topic, get_project_topics,
-                get_subscriber_accounts_by_project_id, get_subscriber_by_topic,
-                get_subscriber_topics, get_subscribers_by_project_id_and_accounts,
-                get_subscribers_for_project_in, get_subscriptions_by_account_and_maybe_app,
-                get_welcome_notification, mark_all_notifications_as_read_for_project,
-                mark_notifications_as_read, set_welcome_notification, upsert_project,
-                upsert_subscriber, GetNotificationsParams, GetNotificationsResult,
-                MarkNotificationsAsReadParams, SubscribeResponse, SubscriberAccountAndScopes,
-                WelcomeNotification,
+                add_subscriber_scopes, begin_with_statement_timeout, check_database,
+                count_active_subscription_watchers, count_expired_subscription_watchers,
+                count_watchers_for_account, delete_expired_subscribers,
+                delete_expired_subscription_watchers, delete_project, delete_subscribers,
+                delete_subscribers_by_project, delete_subscribers_for_account,
+                delete_subscription_watcher, delete_subscription_watchers_for_account,
+                extend_subscription_watcher_expiry, get_account_subscription_details,
+                get_active_subscriptions_by_account, get_all_topics,
+                get_distinct_scopes_for_project, get_distinct_subscriber_account_count,
+                get_expired_subscribers_count, get_notification_types_for_project,
+                get_notifications_for_subscriber, get_notify_targets_for_project_in,
+                get_project_by_app_domain, get_project_by_authentication_public_key,
+                get_project_by_id, get_project_by_project_id, get_project_by_topic,
+                get_project_keys_by_id, get_project_notification_types, get_project_public_by_id,
+                get_project_public_by_project_id, get_project_send_count, get_project_topics,
+                get_project_topics_paginated, get_project_topics_with_timeout, get_projects_by_ids,
+                get_projects_by_project_ids, get_subscriber_accounts_by_project_id,
+                get_subscriber_accounts_with_expiry, get_subscriber_by_id,
+                get_subscriber_by_project_and_account, get_subscriber_by_topic,
+                get_subscriber_counts_by_scope, get_subscriber_delivery_targets,
+                get_subscriber_preferences, get_subscriber_scopes, get_subscriber_topics,
+                get_subscriber_topics_stream, get_subscriber_topics_with_timeout,
+                get_subscriber_with_project_by_topic, get_subscribers_by_project_id_and_accounts,
+                get_subscribers_expiring_before, get_subscribers_expiring_between,
+                get_subscribers_for_project_in, get_subscribers_for_project_in_paginated,
+                get_subscribers_for_project_with_scope, get_subscription_counts_by_app_for_account,
+                get_subscription_watcher_by_did_key,
+                get_subscription_watcher_by_did_key_including_expired,
+                get_subscription_watchers_for_account_and_project,
+                get_subscription_watchers_for_account_by_app_or_all_app,
+                get_subscriptions_by_account_and_maybe_app,
+                get_subscriptions_by_account_and_project_id,
+                get_subscriptions_by_account_updated_after,
+                get_subscriptions_by_account_with_flags, get_subscriptions_by_accounts,
+                get_total_project_count, get_total_subscriber_count, get_unsubscribed_accounts,
+                get_welcome_notification, increment_project_send_count, is_account_subscribed,
+                list_projects, mark_all_notifications_as_read_for_project,
+                mark_notifications_as_read, move_subscriber_to_project, remove_subscriber_scopes,
+                renew_subscribers_for_project, rotate_authentication_key, rotate_subscribe_key,
+                search_projects_by_app_domain_prefix, set_project_notification_types,
+                set_welcome_notification, soft_delete_subscriber, stream_subscribers_for_project,
+                subscribe_and_watch, touch_subscriber_notified, try_get_project_by_topic,
+                try_get_subscriber_by_topic, undelete_subscriber, update_project_app_domain,
+                update_subscriber, update_subscriber_if_unchanged, update_subscriber_with_policy,
+                upsert_notification_type, upsert_project, upsert_project_and_subscriber,
+                upsert_project_tx, upsert_subscriber, upsert_subscriber_preferences,
+                upsert_subscriber_tx, upsert_subscriber_with_policy, upsert_subscriber_with_scope,
+                upsert_subscription_watcher, validate_scopes_against_project, AppDomainError,
+                DeleteProjectError, GetNotificationsParams, GetNotificationsResult,
+                GetProjectKeysError, MarkNotificationsAsReadParams, MoveSubscriberToProjectError,
+                ScanError, ScopeError, ScopePolicy, SubscribeAndWatchError, SubscribeResponse,
+                SubscriberAccountAndScopes, SubscriberPreferences, SubscriptionStatus,
+                UpdateProjectAppDomainError, UpdateSubscriberError,
+                UpsertProjectAndSubscriberError, UpsertProjectError, UpsertSubscriberError,
+                UpsertSubscriberPreferencesError, UpsertSubscriptionWatcherError,
+                ValidateScopeError, WelcomeNotification, MAX_SCOPES,
             },
             types::{
+                caip10::Caip10Error,
                 eip155::test_utils::{format_eip155_account, generate_account, generate_eoa},
-                AccountId,
+                AccountId, Project, SymKey,
             },
         },
         notify_message::NotifyMessage,
@@ -229,6 +276,10 @@ fn generate_account_id() -> AccountId {
     generate_account().1
 }
 
+fn generate_did_key() -> String {
+    DecodedClientId::from_key(&generate_authentication_key().verifying_key()).to_did_key()
+}
+
 #[tokio::test]
 async fn test_one_project() {
     let (postgres, _) = get_postgres().await;
@@ -379,7 +430,7 @@ async fn test_one_subscriber() {
         .unwrap();
     assert_eq!(subscriber.project, project.id);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(subscriber.topic, subscriber_topic);
     assert_eq!(
         subscriber.scope.into_iter().collect::<HashSet<_>>(),
@@ -402,14 +453,14 @@ async fn test_one_subscriber() {
     assert_eq!(accounts, vec![account_id.clone()]);
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let subscriber = &subscribers[0];
     assert_eq!(subscriber.app_domain, project.app_domain);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(subscriber.scope, subscriber_scope);
     assert!(subscriber.expiry > Utc::now() + Duration::days(29));
 }
@@ -488,7 +539,7 @@ async fn test_two_subscribers() {
         .unwrap();
     assert_eq!(subscriber.project, project.id);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(subscriber.topic, subscriber_topic);
     assert_eq!(
         subscriber.scope.into_iter().collect::<HashSet<_>>(),
@@ -501,7 +552,7 @@ async fn test_two_subscribers() {
         .unwrap();
     assert_eq!(subscriber.project, project.id);
     assert_eq!(subscriber.account, account_id2);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key2));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key2));
     assert_eq!(subscriber.topic, subscriber_topic2);
     assert_eq!(
         subscriber.scope.into_iter().collect::<HashSet<_>>(),
@@ -535,26 +586,31 @@ async fn test_two_subscribers() {
     );
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let subscriber = &subscribers[0];
     assert_eq!(subscriber.app_domain, project.app_domain);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(subscriber.scope, subscriber_scope);
     assert!(subscriber.expiry > Utc::now() + Duration::days(29));
 
-    let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id2.clone(), None, &postgres, None)
-            .await
-            .unwrap();
+    let subscribers = get_subscriptions_by_account_and_maybe_app(
+        account_id2.clone(),
+        None,
+        true,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
     assert_eq!(subscribers.len(), 1);
     let subscriber = &subscribers[0];
     assert_eq!(subscriber.app_domain, project.app_domain);
     assert_eq!(subscriber.account, account_id2);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key2));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key2));
     assert_eq!(subscriber.scope, subscriber_scope2);
     assert!(subscriber.expiry > Utc::now() + Duration::days(29));
 }
@@ -654,7 +710,7 @@ async fn test_one_subscriber_two_projects() {
         .unwrap();
     assert_eq!(subscriber.project, project.id);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(subscriber.topic, subscriber_topic);
     assert_eq!(
         subscriber.scope.into_iter().collect::<HashSet<_>>(),
@@ -667,7 +723,7 @@ async fn test_one_subscriber_two_projects() {
         .unwrap();
     assert_eq!(subscriber.project, project2.id);
     assert_eq!(subscriber.account, account_id);
-    assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key2));
+    assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key2));
     assert_eq!(subscriber.topic, subscriber_topic2);
     assert_eq!(
         subscriber.scope.into_iter().collect::<HashSet<_>>(),
@@ -696,127 +752,4491 @@ async fn test_one_subscriber_two_projects() {
     let accounts = get_subscriber_accounts_by_project_id(project_id.clone(), &postgres, None)
         .await
         .unwrap();
-    assert_eq!(accounts, vec![account_id.clone()]);
-    let accounts = get_subscriber_accounts_by_project_id(project_id2.clone(), &postgres, None)
+    assert_eq!(accounts, vec![account_id.clone()]);
+    let accounts = get_subscriber_accounts_by_project_id(project_id2.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(accounts, vec![account_id.clone()]);
+
+    let subscribers =
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscribers.len(), 2);
+    for subscriber in subscribers {
+        if subscriber.app_domain == app_domain.as_ref() {
+            assert_eq!(subscriber.app_domain, app_domain.as_ref());
+            assert_eq!(subscriber.account, account_id);
+            assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key));
+            assert_eq!(subscriber.scope, subscriber_scope);
+            assert!(subscriber.expiry > Utc::now() + Duration::days(29));
+        } else {
+            assert_eq!(subscriber.app_domain, app_domain2.as_ref());
+            assert_eq!(subscriber.account, account_id);
+            assert_eq!(subscriber.sym_key, SymKey::from(subscriber_sym_key2));
+            assert_eq!(subscriber.scope, subscriber_scope2);
+            assert!(subscriber.expiry > Utc::now() + Duration::days(29));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_get_subscriber_accounts_by_project_id() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = &generate_app_domain();
+    let topic = Topic::generate();
+    let subscribe_key = generate_subscribe_key();
+    let authentication_key = generate_authentication_key();
+    upsert_project(
+        project_id.clone(),
+        app_domain,
+        topic,
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let project = get_project_by_project_id(project_id.clone(), &postgres, None)
+        .await
+        .unwrap();
+
+    let account = generate_account_id();
+    let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    upsert_subscriber(
+        project.id,
+        account.clone(),
+        scope,
+        &notify_key,
+        notify_topic,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let accounts = get_subscriber_accounts_by_project_id(project_id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(accounts, vec![account]);
+}
+
+#[tokio::test]
+async fn test_get_subscriber_accounts_and_scopes_by_project_id() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = &generate_app_domain();
+    let topic = Topic::generate();
+    let subscribe_key = generate_subscribe_key();
+    let authentication_key = generate_authentication_key();
+    upsert_project(
+        project_id.clone(),
+        app_domain,
+        topic,
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let project = get_project_by_project_id(project_id.clone(), &postgres, None)
+        .await
+        .unwrap();
+
+    let account = generate_account_id();
+    let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    upsert_subscriber(
+        project.id,
+        account.clone(),
+        scope.clone(),
+        &notify_key,
+        notify_topic,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let subscribers =
+        get_subscribers_by_project_id_and_accounts(project_id, &[account.clone()], &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(
+        subscribers,
+        vec![SubscriberAccountAndScopes { account, scope }]
+    );
+}
+
+#[tokio::test]
+async fn test_get_projects_by_project_ids() {
+    let (postgres, _) = get_postgres().await;
+
+    let mut project_ids = Vec::new();
+    for _ in 0..2 {
+        let project_id = ProjectId::generate();
+        upsert_project(
+            project_id.clone(),
+            &generate_app_domain(),
+            Topic::generate(),
+            &generate_authentication_key(),
+            &generate_subscribe_key(),
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+        project_ids.push(project_id);
+    }
+
+    let projects = get_projects_by_project_ids(
+        &[
+            project_ids[0].clone(),
+            project_ids[1].clone(),
+            ProjectId::generate(),
+        ],
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        projects
+            .into_iter()
+            .map(|project| project.project_id)
+            .collect::<HashSet<_>>(),
+        project_ids.into_iter().collect::<HashSet<_>>(),
+    );
+}
+
+#[tokio::test]
+async fn test_count_active_subscription_watchers() {
+    let (postgres, _) = get_postgres().await;
+
+    assert_eq!(
+        count_active_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+
+    let account = generate_account_id();
+    upsert_subscription_watcher(
+        account.clone(),
+        None,
+        &generate_did_key(),
+        &hex::encode(rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng())),
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    upsert_subscription_watcher(
+        account.clone(),
+        None,
+        &generate_did_key(),
+        &hex::encode(rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng())),
+        Utc::now() - Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        count_active_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        count_watchers_for_account(&account, &postgres, None)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        count_watchers_for_account(&generate_account_id(), &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriptions_by_account_with_flags() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() + Duration::days(1))
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let subscriptions =
+        get_subscriptions_by_account_with_flags(account, Duration::days(2), &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 2);
+    let expiring_soon = subscriptions
+        .into_iter()
+        .map(|s| (s.subscriber.app_domain, s.expiring_soon))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(
+        expiring_soon,
+        HashMap::from([(project1.app_domain, false), (project2.app_domain, true)])
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_project_inserted() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = generate_app_domain();
+    let topic = Topic::generate();
+    let authentication_key = generate_authentication_key();
+    let subscribe_key = generate_subscribe_key();
+    let result = upsert_project(
+        project_id.clone(),
+        &app_domain,
+        topic.clone(),
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(result.inserted);
+
+    let result = upsert_project(
+        project_id,
+        &app_domain,
+        topic,
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(!result.inserted);
+    assert_eq!(
+        result.keys.authentication_public_key,
+        encode_authentication_public_key(&authentication_key)
+    );
+}
+
+#[tokio::test]
+async fn test_list_projects() {
+    let (postgres, _) = get_postgres().await;
+
+    let mut project_ids = HashSet::new();
+    for _ in 0..3 {
+        project_ids.insert(helper_upsert_project(&postgres).await.id);
+    }
+
+    let page1 = list_projects(None, 2, &postgres, None).await.unwrap();
+    assert_eq!(page1.len(), 2);
+    assert!(page1[0].id < page1[1].id);
+    assert!(page1[0].created_at <= page1[0].updated_at);
+    let page2 = list_projects(Some(page1[1].id), 2, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(page2.len(), 1);
+    let page3 = list_projects(Some(page2[0].id), 2, &postgres, None)
+        .await
+        .unwrap();
+    assert!(page3.is_empty());
+
+    assert_eq!(
+        page1
+            .into_iter()
+            .chain(page2)
+            .map(|project| project.id)
+            .collect::<HashSet<_>>(),
+        project_ids
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_empty_scope() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+
+    let result = upsert_subscriber_with_policy(
+        project.id,
+        account.clone(),
+        HashSet::new(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriberError::Scope(ScopeError::Empty))
+    ));
+    assert!(
+        get_subscriber_by_topic(notify_topic.clone(), &postgres, None)
+            .await
+            .is_err()
+    );
+
+    let subscriber = upsert_subscriber(
+        project.id,
+        account.clone(),
+        HashSet::new(),
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let result = update_subscriber_with_policy(
+        subscriber.id,
+        HashSet::new(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::Empty))
+    ));
+
+    let scope = HashSet::from([Uuid::new_v4()]);
+    update_subscriber_with_policy(
+        subscriber.id,
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let subscriber = get_subscriber_by_topic(notify_topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.scope, scope);
+}
+
+#[tokio::test]
+async fn test_get_account_subscription_details() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let details = get_account_subscription_details(account, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(details.len(), 2);
+    let statuses = details
+        .into_iter()
+        .map(|detail| (detail.subscription.app_domain, detail.status))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(
+        statuses,
+        HashMap::from([
+            (project1.app_domain, SubscriptionStatus::Active),
+            (project2.app_domain, SubscriptionStatus::Expired),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriber_scopes() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
+    update_subscriber_with_policy(
+        subscriber.id,
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_subscriber_scopes(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        scope
+    );
+    assert!(get_subscriber_scopes(Uuid::new_v4(), &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_get_subscriber_by_project_and_account() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    let scope = HashSet::from([Uuid::new_v4()]);
+    update_subscriber_with_policy(
+        subscriber.id,
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let result =
+        get_subscriber_by_project_and_account(project.id, account.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .unwrap();
+    assert_eq!(result.id, subscriber.id);
+    assert_eq!(result.account, account);
+    assert_eq!(result.scope, scope);
+
+    assert!(get_subscriber_by_project_and_account(
+        project.id,
+        generate_account_id(),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_none());
+    let other_project = helper_upsert_project(&postgres).await;
+    assert!(
+        get_subscriber_by_project_and_account(other_project.id, account, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_subscribe_and_watch() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let scope = HashSet::from([Uuid::new_v4()]);
+    let watcher_did_key = generate_did_key();
+    let subscriber = subscribe_and_watch(
+        project.id,
+        account.clone(),
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &watcher_did_key,
+        &hex::encode(generate_subscribe_key().to_bytes()),
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let result = get_subscriber_by_topic(notify_topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(result.id, subscriber);
+    assert_eq!(result.scope, scope);
+    let watchers = get_subscription_watchers_for_account_by_app_or_all_app(
+        &account,
+        &project.app_domain,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(watchers.len(), 1);
+    assert_eq!(watchers[0].did_key, watcher_did_key);
+}
+
+#[tokio::test]
+async fn test_subscribe_and_watch_rolls_back_subscriber() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    for _ in 0..SUBSCRIPTION_WATCHER_LIMIT {
+        upsert_subscription_watcher(
+            account.clone(),
+            Some(project.id),
+            &generate_did_key(),
+            &hex::encode(generate_subscribe_key().to_bytes()),
+            Utc::now() + Duration::days(1),
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let result = subscribe_and_watch(
+        project.id,
+        account.clone(),
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &generate_did_key(),
+        &hex::encode(generate_subscribe_key().to_bytes()),
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(SubscribeAndWatchError::WatcherLimitReached)
+    ));
+    assert!(
+        get_subscriber_by_project_and_account(project.id, account, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_add_and_remove_subscriber_scopes() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let scope1 = Uuid::new_v4();
+    let scope2 = Uuid::new_v4();
+    let scope3 = Uuid::new_v4();
+    update_subscriber_with_policy(
+        subscriber.id,
+        HashSet::from([scope1, scope2]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let before = get_subscriber_by_id(subscriber.id, &postgres, None)
+        .await
+        .unwrap()
+        .updated_at;
+    add_subscriber_scopes(
+        subscriber.id,
+        HashSet::from([scope2, scope3]),
+        ScopePolicy::default(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let after_add = get_subscriber_by_id(subscriber.id, &postgres, None)
+        .await
+        .unwrap()
+        .updated_at;
+    assert!(after_add > before);
+    assert_eq!(
+        get_subscriber_scopes(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashSet::from([scope1, scope2, scope3])
+    );
+
+    remove_subscriber_scopes(
+        subscriber.id,
+        HashSet::from([scope1, Uuid::new_v4()]),
+        ScopePolicy::default(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(
+        get_subscriber_by_id(subscriber.id, &postgres, None)
+            .await
+            .unwrap()
+            .updated_at
+            > after_add
+    );
+    assert_eq!(
+        get_subscriber_scopes(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashSet::from([scope2, scope3])
+    );
+
+    let result = add_subscriber_scopes(
+        Uuid::new_v4(),
+        HashSet::from([scope1]),
+        ScopePolicy::default(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Sqlx(sqlx::Error::RowNotFound))
+    ));
+}
+
+#[tokio::test]
+async fn test_add_and_remove_subscriber_scopes_validates_result() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let declared = Uuid::new_v4();
+    set_project_notification_types(project.id, HashSet::from([declared]), &postgres, None)
+        .await
+        .unwrap();
+    update_subscriber(subscriber.id, HashSet::from([declared]), &postgres, None)
+        .await
+        .unwrap();
+    let policy = ScopePolicy {
+        require_non_empty: true,
+        enforce_declared: true,
+    };
+
+    // Removing the only notification type would leave the scope empty
+    let result = remove_subscriber_scopes(
+        subscriber.id,
+        HashSet::from([declared]),
+        policy,
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::Empty))
+    ));
+
+    let undeclared = Uuid::new_v4();
+    let result = add_subscriber_scopes(
+        subscriber.id,
+        HashSet::from([undeclared]),
+        policy,
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::Undeclared(scopes))) if scopes == vec![undeclared]
+    ));
+
+    // The checks count the existing scope too, not just the added types
+    let too_many = (0..MAX_SCOPES)
+        .map(|_| Uuid::new_v4())
+        .collect::<HashSet<_>>();
+    let result = add_subscriber_scopes(
+        subscriber.id,
+        too_many,
+        ScopePolicy::default(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::TooMany(count))) if count == MAX_SCOPES + 1
+    ));
+
+    // Failed modifications are rolled back
+    assert_eq!(
+        get_subscriber_scopes(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashSet::from([declared])
+    );
+}
+
+#[tokio::test]
+async fn test_soft_delete_subscriber() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+
+    assert!(soft_delete_subscriber(subscriber.id, &postgres, None)
+        .await
+        .unwrap());
+    assert!(matches!(
+        get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+    assert!(
+        get_subscribers_for_project_in(project.id, &[account.clone()], &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(get_subscriptions_by_account_and_maybe_app(
+        account.clone(),
+        None,
+        true,
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+    assert!(
+        get_subscriber_accounts_by_project_id(project.project_id.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(get_subscribers_by_project_id_and_accounts(
+        project.project_id.clone(),
+        &[account.clone()],
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+    assert!(
+        get_subscriber_by_project_and_account(project.id, account.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(get_subscriptions_by_account_with_flags(
+        account.clone(),
+        Duration::days(1),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+    assert!(get_subscriber_topics(&postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    assert!(undelete_subscriber(subscriber.id, &postgres, None)
+        .await
+        .unwrap());
+    assert_eq!(
+        get_subscriptions_by_account_and_maybe_app(account.clone(), None, true, &postgres, None)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        get_subscriber_topics(&postgres, None).await.unwrap(),
+        vec![subscriber.topic.clone()]
+    );
+    let result = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(result.id, subscriber.id);
+    let subscribers = get_subscribers_for_project_in(project.id, &[account], &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].id, subscriber.id);
+
+    assert!(!soft_delete_subscriber(Uuid::new_v4(), &postgres, None)
+        .await
+        .unwrap());
+    assert!(!undelete_subscriber(Uuid::new_v4(), &postgres, None)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_restores_soft_deleted() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    soft_delete_subscriber(subscriber.id, &postgres, None)
+        .await
+        .unwrap();
+
+    let resubscribed = helper_upsert_subscriber(project.id, account, &postgres).await;
+    assert_eq!(resubscribed.id, subscriber.id);
+    assert!(!resubscribed.inserted);
+    get_subscriber_by_topic(subscriber.topic, &postgres, None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_upsert_project_topic_collision() {
+    let (postgres, _) = get_postgres().await;
+
+    let topic = Topic::generate();
+    upsert_project(
+        ProjectId::generate(),
+        &generate_app_domain(),
+        topic.clone(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let result = upsert_project(
+        ProjectId::generate(),
+        &generate_app_domain(),
+        topic.clone(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertProjectError::TopicCollision(t)) if t == topic
+    ));
+}
+
+#[tokio::test]
+async fn test_delete_subscribers() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber1 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let subscriber3 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    assert_eq!(delete_subscribers(&[], &postgres, None).await.unwrap(), 0);
+
+    let deleted = delete_subscribers(
+        &[subscriber1.id, subscriber2.id, Uuid::new_v4()],
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(deleted, 2);
+
+    assert!(get_subscriber_by_topic(subscriber1.topic, &postgres, None)
+        .await
+        .is_err());
+    assert!(get_subscriber_by_topic(subscriber2.topic, &postgres, None)
+        .await
+        .is_err());
+    get_subscriber_by_topic(subscriber3.topic, &postgres, None)
+        .await
+        .unwrap();
+
+    let orphaned_scopes = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM subscriber_scope WHERE subscriber=ANY($1)",
+    )
+    .bind(vec![subscriber1.id, subscriber2.id])
+    .fetch_one(&postgres)
+    .await
+    .unwrap();
+    assert_eq!(orphaned_scopes, 0);
+    assert_eq!(
+        get_subscriber_scopes(subscriber3.id, &postgres, None)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_delete_subscribers_by_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+    let subscriber = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+
+    let deleted = delete_subscribers_by_project(project1.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(deleted, 2);
+    assert!(
+        get_subscriber_accounts_by_project_id(project1.project_id.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let remaining = get_subscriber_by_topic(subscriber.topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(remaining.id, subscriber.id);
+    assert_eq!(remaining.scope.len(), 2);
+
+    let orphaned_scopes = sqlx::query_scalar::<_, i64>(
+        "
+        SELECT COUNT(*)
+        FROM subscriber_scope
+        LEFT JOIN subscriber ON subscriber.id=subscriber_scope.subscriber
+        WHERE subscriber.id IS NULL
+        ",
+    )
+    .fetch_one(&postgres)
+    .await
+    .unwrap();
+    assert_eq!(orphaned_scopes, 0);
+
+    assert_eq!(
+        delete_subscribers_by_project(project1.id, &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriptions_by_account_updated_after() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+
+    let subscriptions =
+        get_subscriptions_by_account_updated_after(account.clone(), None, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 2);
+    let cursor = subscriptions
+        .iter()
+        .map(|subscription| subscription.updated_at)
+        .max()
+        .unwrap();
+
+    assert!(get_subscriptions_by_account_updated_after(
+        account.clone(),
+        Some(cursor),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+
+    update_subscriber_with_policy(
+        subscriber2.id,
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let subscriptions =
+        get_subscriptions_by_account_updated_after(account.clone(), Some(cursor), &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].app_domain, project2.app_domain);
+    assert!(subscriptions[0].updated_at > cursor);
+
+    soft_delete_subscriber(subscriber2.id, &postgres, None)
+        .await
+        .unwrap();
+    assert!(get_subscriptions_by_account_updated_after(
+        account.clone(),
+        Some(cursor),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+    let subscriptions = get_subscriptions_by_account_updated_after(account, None, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].app_domain, project1.app_domain);
+}
+
+#[tokio::test]
+async fn test_delete_expired_subscribers() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let active = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let expired = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        get_expired_subscribers_count(&postgres, None)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        delete_expired_subscribers(&postgres, None).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        get_expired_subscribers_count(&postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        delete_expired_subscribers(&postgres, None).await.unwrap(),
+        0
+    );
+
+    assert!(get_subscriber_by_topic(expired.topic, &postgres, None)
+        .await
+        .is_err());
+    assert!(get_subscriber_scopes(expired.id, &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+    get_subscriber_by_topic(active.topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_subscriber_scopes(active.id, &postgres, None)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_delete_expired_subscribers_keeps_soft_deleted() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let unexpired = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let expired = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    for subscriber in [&unexpired, &expired] {
+        assert!(soft_delete_subscriber(subscriber.id, &postgres, None)
+            .await
+            .unwrap());
+    }
+
+    delete_expired_subscribers(&postgres, None).await.unwrap();
+
+    // Both rows survive the purge and can still be restored
+    for subscriber in [&unexpired, &expired] {
+        assert!(undelete_subscriber(subscriber.id, &postgres, None)
+            .await
+            .unwrap());
+        assert_eq!(
+            get_subscriber_by_id(subscriber.id, &postgres, None)
+                .await
+                .unwrap()
+                .id,
+            subscriber.id
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_get_all_topics() {
+    let (postgres, _) = get_postgres().await;
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber1 = helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, generate_account_id(), &postgres).await;
+    let deleted = helper_upsert_subscriber(project2.id, generate_account_id(), &postgres).await;
+    soft_delete_subscriber(deleted.id, &postgres, None)
+        .await
+        .unwrap();
+
+    // Soft-deleted subscribers' topics aren't renewed with the relay
+    assert_eq!(
+        get_all_topics(&postgres, None).await.unwrap(),
+        HashSet::from([
+            project1.topic,
+            project2.topic,
+            subscriber1.topic,
+            subscriber2.topic,
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_get_project_public() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+
+    let by_id = get_project_public_by_id(project.id, &postgres, None)
+        .await
+        .unwrap();
+    let by_project_id =
+        get_project_public_by_project_id(project.project_id.clone(), &postgres, None)
+            .await
+            .unwrap();
+    for public in [by_id, by_project_id] {
+        assert_eq!(public.id, project.id);
+        assert_eq!(public.project_id, project.project_id);
+        assert_eq!(public.app_domain, project.app_domain);
+        assert_eq!(public.topic, project.topic);
+        assert_eq!(
+            public.authentication_public_key,
+            project.authentication_public_key
+        );
+        assert_eq!(public.subscribe_public_key, project.subscribe_public_key);
+        assert_eq!(public.created_at, project.created_at);
+        assert_eq!(public.updated_at, project.updated_at);
+    }
+}
+
+#[tokio::test]
+async fn test_get_subscription_watcher_by_did_key() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let did_key = generate_did_key();
+    let sym_key = hex::encode(generate_subscribe_key().to_bytes());
+    upsert_subscription_watcher(
+        account.clone(),
+        Some(project.id),
+        &did_key,
+        &sym_key,
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let watcher = get_subscription_watcher_by_did_key(&did_key, &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(watcher.account, account);
+    assert_eq!(watcher.project, Some(project.id));
+    assert_eq!(watcher.did_key, did_key);
+    assert_eq!(watcher.sym_key, sym_key);
+    assert!(watcher.expiry > Utc::now());
+
+    assert!(
+        get_subscription_watcher_by_did_key(&generate_did_key(), &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let expired_did_key = generate_did_key();
+    upsert_subscription_watcher(
+        account,
+        Some(project.id),
+        &expired_did_key,
+        &hex::encode(generate_subscribe_key().to_bytes()),
+        Utc::now() - Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(
+        get_subscription_watcher_by_did_key(&expired_did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let expired_watcher =
+        get_subscription_watcher_by_did_key_including_expired(&expired_did_key, &postgres, None)
+            .await
+            .unwrap()
+            .unwrap();
+    assert_eq!(expired_watcher.did_key, expired_did_key);
+    assert!(expired_watcher.expiry < Utc::now());
+
+    let watcher = get_subscription_watcher_by_did_key_including_expired(&did_key, &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(watcher.expiry > Utc::now());
+    assert!(get_subscription_watcher_by_did_key_including_expired(
+        &generate_did_key(),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[tokio::test]
+async fn test_get_subscribers_for_project_with_scope() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let scope1 = Uuid::new_v4();
+    let scope2 = Uuid::new_v4();
+    let subscriber1 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber1.id,
+        HashSet::from([scope1, scope2]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let subscriber2 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber2.id,
+        HashSet::from([scope2]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let other_project = helper_upsert_project(&postgres).await;
+    let other_subscriber =
+        helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        other_subscriber.id,
+        HashSet::from([scope1]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let subscribers = get_subscribers_for_project_with_scope(project.id, scope1, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].id, subscriber1.id);
+    assert_eq!(subscribers[0].scope, HashSet::from([scope1, scope2]));
+
+    let subscribers = get_subscribers_for_project_with_scope(project.id, scope2, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        subscribers
+            .into_iter()
+            .map(|s| s.id)
+            .collect::<HashSet<_>>(),
+        HashSet::from([subscriber1.id, subscriber2.id])
+    );
+
+    assert!(
+        get_subscribers_for_project_with_scope(project.id, Uuid::new_v4(), &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_delete_subscription_watcher() {
+    let (postgres, _) = get_postgres().await;
+
+    let did_key = generate_did_key();
+    upsert_subscription_watcher(
+        generate_account_id(),
+        None,
+        &did_key,
+        &hex::encode(generate_subscribe_key().to_bytes()),
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert!(delete_subscription_watcher(&did_key, &postgres, None)
+        .await
+        .unwrap());
+    assert!(
+        get_subscription_watcher_by_did_key(&did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(!delete_subscription_watcher(&did_key, &postgres, None)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_get_project_by_authentication_public_key() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+
+    let result = get_project_by_authentication_public_key(
+        &project.authentication_public_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(result.id, project.id);
+
+    let unknown_key = encode_authentication_public_key(&generate_authentication_key());
+    assert!(
+        get_project_by_authentication_public_key(&unknown_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_check_database() {
+    let (postgres, _) = get_postgres().await;
+
+    check_database(&postgres, None).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_upsert_project_timestamps() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = generate_app_domain();
+    let topic = Topic::generate();
+    let authentication_key = generate_authentication_key();
+    let subscribe_key = generate_subscribe_key();
+    let first = upsert_project(
+        project_id.clone(),
+        &app_domain,
+        topic.clone(),
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(first.keys.created_at, first.keys.updated_at);
+
+    let second = upsert_project(
+        project_id,
+        &app_domain,
+        topic,
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(second.keys.created_at, first.keys.created_at);
+    assert!(second.keys.updated_at > first.keys.updated_at);
+}
+
+#[tokio::test]
+async fn test_upsert_project_and_subscriber() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = generate_app_domain();
+    let account = generate_account_id();
+    let scope = HashSet::from([Uuid::new_v4()]);
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let (project_keys, subscriber) = upsert_project_and_subscriber(
+        project_id.clone(),
+        &app_domain,
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        account.clone(),
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let project = get_project_by_project_id(project_id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(project.id, project_keys.id);
+    assert_eq!(
+        project.authentication_public_key,
+        project_keys.authentication_public_key
+    );
+    let result = get_subscriber_by_topic(notify_topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(result.id, subscriber);
+    assert_eq!(result.project, project.id);
+    assert_eq!(result.account, account);
+    assert_eq!(result.scope, scope);
+}
+
+#[tokio::test]
+async fn test_upsert_project_and_subscriber_rolls_back_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let existing_project = helper_upsert_project(&postgres).await;
+    let existing_subscriber =
+        helper_upsert_subscriber(existing_project.id, generate_account_id(), &postgres).await;
+
+    // Reusing the existing subscriber's topic makes the subscriber insert fail
+    let project_id = ProjectId::generate();
+    let result = upsert_project_and_subscriber(
+        project_id.clone(),
+        &generate_app_domain(),
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        generate_account_id(),
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng()),
+        existing_subscriber.topic,
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertProjectAndSubscriberError::Sqlx(_))
+    ));
+    assert!(matches!(
+        get_project_by_project_id(project_id, &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_update_project_app_domain() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let new_app_domain = generate_app_domain();
+    let updated =
+        update_project_app_domain(project.project_id.clone(), &new_app_domain, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(updated.id, project.id);
+    assert_eq!(updated.app_domain, new_app_domain.as_ref());
+    assert_eq!(updated.topic, project.topic);
+    assert_eq!(updated.subscribe_public_key, project.subscribe_public_key);
+    assert!(updated.updated_at > project.updated_at);
+
+    let result = get_project_by_app_domain(&new_app_domain, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(result.id, project.id);
+
+    assert!(matches!(
+        update_project_app_domain(
+            ProjectId::generate(),
+            &generate_app_domain(),
+            &postgres,
+            None
+        )
+        .await,
+        Err(UpdateProjectAppDomainError::Sqlx(sqlx::Error::RowNotFound))
+    ));
+}
+
+#[tokio::test]
+async fn test_get_project_topics_paginated() {
+    let (postgres, _) = get_postgres().await;
+
+    let mut topics = HashSet::new();
+    for _ in 0..3 {
+        topics.insert(helper_upsert_project(&postgres).await.topic);
+    }
+
+    let (page1, after) = get_project_topics_paginated(None, 2, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(page1.len(), 2);
+    let (page2, after) = get_project_topics_paginated(after, 2, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(page2.len(), 1);
+    let (page3, after) = get_project_topics_paginated(after, 2, &postgres, None)
+        .await
+        .unwrap();
+    assert!(page3.is_empty());
+    assert!(after.is_none());
+
+    assert_eq!(
+        page1.into_iter().chain(page2).collect::<HashSet<_>>(),
+        topics
+    );
+
+    // Out of range limits are clamped rather than passed to Postgres
+    let (page, _) = get_project_topics_paginated(None, -1, &postgres, None)
+        .await
+        .unwrap();
+    assert!(page.is_empty());
+    let (page, _) = get_project_topics_paginated(None, i64::MAX, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(page.len(), 3);
+}
+
+#[tokio::test]
+async fn test_rotate_project_keys() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+
+    let subscribe_key = generate_subscribe_key();
+    let subscribe_public_key =
+        rotate_subscribe_key(project.project_id.clone(), &subscribe_key, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(
+        subscribe_public_key,
+        encode_subscribe_public_key(&subscribe_key)
+    );
+    let rotated = get_project_by_id(project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(rotated.subscribe_public_key, subscribe_public_key);
+    assert_eq!(
+        rotated.subscribe_private_key,
+        encode_subscribe_private_key(&subscribe_key)
+    );
+    assert_eq!(
+        rotated.topic,
+        topic_from_key(x25519_dalek::PublicKey::from(&subscribe_key).as_bytes())
+    );
+    assert_eq!(
+        rotated.authentication_public_key,
+        project.authentication_public_key
+    );
+    assert_eq!(rotated.app_domain, project.app_domain);
+    assert!(rotated.updated_at > project.updated_at);
+
+    let authentication_key = generate_authentication_key();
+    let authentication_public_key = rotate_authentication_key(
+        project.project_id.clone(),
+        &authentication_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        authentication_public_key,
+        encode_authentication_public_key(&authentication_key)
+    );
+    let rotated = get_project_by_id(project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(rotated.authentication_public_key, authentication_public_key);
+    assert_eq!(
+        rotated.authentication_private_key,
+        encode_authentication_private_key(&authentication_key)
+    );
+    assert_eq!(rotated.subscribe_public_key, subscribe_public_key);
+
+    assert!(matches!(
+        rotate_subscribe_key(
+            ProjectId::generate(),
+            &generate_subscribe_key(),
+            &postgres,
+            None
+        )
+        .await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+    assert!(matches!(
+        rotate_authentication_key(
+            ProjectId::generate(),
+            &generate_authentication_key(),
+            &postgres,
+            None
+        )
+        .await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_get_subscriptions_by_account_and_maybe_app_include_expired() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let expired = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let subscriptions =
+        get_subscriptions_by_account_and_maybe_app(account.clone(), None, false, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].app_domain, project1.app_domain);
+
+    assert!(get_subscriptions_by_account_and_maybe_app(
+        account.clone(),
+        Some(&project2.app_domain),
+        false,
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+
+    let subscriptions =
+        get_subscriptions_by_account_and_maybe_app(account, None, true, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 2);
+}
+
+#[tokio::test]
+async fn test_get_subscriber_topics_stream() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber1 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    let topics = get_subscriber_topics_stream(&postgres, None)
+        .try_collect::<HashSet<_>>()
+        .await
+        .unwrap();
+    assert_eq!(
+        topics,
+        HashSet::from([subscriber1.topic, subscriber2.topic])
+    );
+    assert_eq!(
+        topics,
+        get_subscriber_topics(&postgres, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>()
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_project_and_subscriber_tx() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+
+    let mut txn = postgres.begin().await.unwrap();
+    let project = upsert_project_tx(
+        project_id.clone(),
+        &generate_app_domain(),
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &mut txn,
+        None,
+    )
+    .await
+    .unwrap();
+    upsert_subscriber_tx(
+        project.keys.id,
+        account.clone(),
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy::default(),
+        &notify_key,
+        topic_from_key(&notify_key),
+        &mut txn,
+        None,
+    )
+    .await
+    .unwrap();
+    txn.rollback().await.unwrap();
+
+    assert!(matches!(
+        get_project_by_project_id(project_id.clone(), &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+
+    let mut txn = postgres.begin().await.unwrap();
+    let project = upsert_project_tx(
+        project_id.clone(),
+        &generate_app_domain(),
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &mut txn,
+        None,
+    )
+    .await
+    .unwrap();
+    let subscriber = upsert_subscriber_tx(
+        project.keys.id,
+        account.clone(),
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy::default(),
+        &notify_key,
+        topic_from_key(&notify_key),
+        &mut txn,
+        None,
+    )
+    .await
+    .unwrap();
+    txn.commit().await.unwrap();
+
+    let project = get_project_by_project_id(project_id, &postgres, None)
+        .await
+        .unwrap();
+    let subscriber = get_subscriber_by_topic(subscriber.topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.project, project.id);
+    assert_eq!(subscriber.account, account);
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_too_many_scopes() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let too_many_scopes = (0..=MAX_SCOPES)
+        .map(|_| Uuid::new_v4())
+        .collect::<HashSet<_>>();
+
+    let result = upsert_subscriber(
+        project.id,
+        account.clone(),
+        too_many_scopes.clone(),
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriberError::Scope(ScopeError::TooMany(count))) if count == MAX_SCOPES + 1
+    ));
+    assert!(
+        get_subscriber_by_topic(notify_topic.clone(), &postgres, None)
+            .await
+            .is_err()
+    );
+
+    let scope = HashSet::from([Uuid::new_v4()]);
+    let subscriber = upsert_subscriber(
+        project.id,
+        account,
+        scope.clone(),
+        &notify_key,
+        notify_topic,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let result = update_subscriber(subscriber.id, too_many_scopes, &postgres, None).await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::TooMany(_)))
+    ));
+    assert_eq!(
+        get_subscriber_scopes(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        scope
+    );
+}
+
+#[tokio::test]
+async fn test_try_get_subscriber_by_topic() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+
+    let found = try_get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, subscriber.id);
+    assert_eq!(found.account, account);
+
+    assert!(
+        try_get_subscriber_by_topic(Topic::generate(), &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(matches!(
+        get_subscriber_by_topic(Topic::generate(), &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_invalid_account() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+
+    let result = upsert_subscriber(
+        project.id,
+        AccountId::from(Arc::<str>::from("junk")),
+        HashSet::from([Uuid::new_v4()]),
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriberError::InvalidAccount(Caip10Error::Invalid))
+    ));
+    assert!(try_get_subscriber_by_topic(notify_topic, &postgres, None)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(
+        get_subscriber_accounts_by_project_id(project.project_id, &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_get_active_subscriptions_by_account() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let subscriptions = get_active_subscriptions_by_account(account.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].app_domain, project1.app_domain);
+
+    assert_eq!(
+        get_subscriptions_by_account_and_maybe_app(account, None, true, &postgres, None)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_with_scope() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
+
+    let outcome = upsert_subscriber_with_scope(
+        project.id,
+        account.clone(),
+        scope.clone(),
+        ScopePolicy::default(),
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(outcome.inserted);
+
+    let subscriber = get_subscriber_by_topic(notify_topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(outcome.subscriber.id, subscriber.id);
+    assert_eq!(outcome.subscriber.project, project.id);
+    assert_eq!(outcome.subscriber.account, account);
+    assert_eq!(outcome.subscriber.sym_key, subscriber.sym_key);
+    assert_eq!(outcome.subscriber.topic, notify_topic);
+    assert_eq!(outcome.subscriber.scope, scope);
+    assert_eq!(outcome.subscriber.scope, subscriber.scope);
+    assert_eq!(outcome.subscriber.expiry, subscriber.expiry);
+
+    let scope = HashSet::from([Uuid::new_v4()]);
+    let outcome = upsert_subscriber_with_scope(
+        project.id,
+        account,
+        scope.clone(),
+        ScopePolicy::default(),
+        &notify_key,
+        notify_topic,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(!outcome.inserted);
+    assert_eq!(outcome.subscriber.id, subscriber.id);
+    assert_eq!(outcome.subscriber.scope, scope);
+}
+
+#[tokio::test]
+async fn test_is_account_subscribed() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    assert!(
+        !is_account_subscribed(project.id, account.clone(), &postgres, None)
+            .await
+            .unwrap()
+    );
+
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    assert!(
+        is_account_subscribed(project.id, account.clone(), &postgres, None)
+            .await
+            .unwrap()
+    );
+    assert!(!is_account_subscribed(
+        helper_upsert_project(&postgres).await.id,
+        account.clone(),
+        &postgres,
+        None
+    )
+    .await
+    .unwrap());
+
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(subscriber.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    assert!(!is_account_subscribed(project.id, account, &postgres, None)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn test_touch_subscriber_notified() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    assert!(
+        get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .last_notified_at
+            .is_none()
+    );
+
+    let before = Utc::now();
+    touch_subscriber_notified(subscriber.id, &postgres, None)
+        .await
+        .unwrap();
+    let last_notified_at = get_subscriber_by_topic(subscriber.topic, &postgres, None)
+        .await
+        .unwrap()
+        .last_notified_at
+        .unwrap();
+    assert!(last_notified_at > before - Duration::seconds(5));
+    assert!(last_notified_at < Utc::now() + Duration::seconds(5));
+}
+
+#[tokio::test]
+async fn test_get_subscriber_counts_by_scope() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    assert!(get_subscriber_counts_by_scope(project.id, &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let scope1 = Uuid::new_v4();
+    let scope2 = Uuid::new_v4();
+    let subscriber1 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber1.id,
+        HashSet::from([scope1, scope2]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let subscriber2 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber2.id,
+        HashSet::from([scope1]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO subscriber_scope (subscriber, name) VALUES ($1, 'not-a-uuid')")
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    // Other projects aren't counted
+    let other_project = helper_upsert_project(&postgres).await;
+    let other_subscriber =
+        helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        other_subscriber.id,
+        HashSet::from([scope1]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_subscriber_counts_by_scope(project.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashMap::from([(scope1, 2), (scope2, 1)])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscribers_expiring_between() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let expiring = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() + Duration::days(2))
+        .bind(expiring.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    // Default expiry is 30 days out, outside of the window
+    helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let other_expiring =
+        helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() + Duration::days(2))
+        .bind(other_expiring.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let subscribers = get_subscribers_expiring_between(
+        project.id,
+        Utc::now(),
+        Utc::now() + Duration::days(3),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0].id, expiring.id);
+    assert_eq!(subscribers[0].topic, expiring.topic);
+}
+
+#[tokio::test]
+async fn test_app_domain_normalized() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = generate_app_domain();
+    let mixed_case_fqdn = format!("{}.", app_domain.to_ascii_uppercase());
+    let project = upsert_project(
+        project_id.clone(),
+        &mixed_case_fqdn,
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let stored = get_project_by_project_id(project_id.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(stored.app_domain, app_domain.as_ref());
+
+    for lookup in [app_domain.to_string(), mixed_case_fqdn] {
+        let found = get_project_by_app_domain(&lookup, &postgres, None)
+            .await
+            .unwrap();
+        assert_eq!(found.id, project.keys.id);
+    }
+
+    // Differently cased domain is the same project, so another project can't take it
+    let result = upsert_project(
+        ProjectId::generate(),
+        &app_domain.to_ascii_uppercase(),
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertProjectError::Sqlx(sqlx::Error::Database(e))) if e.is_unique_violation()
+    ));
+
+    let result = upsert_project(
+        ProjectId::generate(),
+        "not a domain",
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertProjectError::InvalidAppDomain(
+            AppDomainError::Invalid
+        ))
+    ));
+
+    assert!(matches!(
+        update_project_app_domain(project_id, "not a domain", &postgres, None).await,
+        Err(UpdateProjectAppDomainError::InvalidAppDomain(
+            AppDomainError::Invalid
+        ))
+    ));
+
+    // Writes that bypass normalize_app_domain() are rejected by the database
+    for raw in [app_domain.to_ascii_uppercase(), format!("{app_domain}.")] {
+        let result = sqlx::query("UPDATE project SET app_domain=$1 WHERE id=$2")
+            .bind(raw)
+            .bind(project.keys.id)
+            .execute(&postgres)
+            .await;
+        assert!(matches!(
+            result,
+            Err(sqlx::Error::Database(e)) if e.constraint() == Some("project_app_domain_normalized")
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_app_domain_www_is_kept() {
+    let (postgres, _) = get_postgres().await;
+
+    let app_domain = generate_app_domain();
+    let www_app_domain = format!("www.{app_domain}");
+    let mut projects = Vec::new();
+    for domain in [app_domain.to_string(), www_app_domain.clone()] {
+        let project_id = ProjectId::generate();
+        upsert_project(
+            project_id.clone(),
+            &domain,
+            Topic::generate(),
+            &generate_authentication_key(),
+            &generate_subscribe_key(),
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+        projects.push(
+            get_project_by_project_id(project_id, &postgres, None)
+                .await
+                .unwrap(),
+        );
+    }
+    assert_ne!(projects[0].id, projects[1].id);
+    assert_eq!(projects[1].app_domain, www_app_domain);
+
+    let found = get_project_by_app_domain(
+        &format!("{}.", www_app_domain.to_ascii_uppercase()),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(found.id, projects[1].id);
+}
+
+#[tokio::test]
+async fn test_upsert_project_idempotency() {
+    let (postgres, _) = get_postgres().await;
+
+    let project_id = ProjectId::generate();
+    let app_domain = generate_app_domain();
+    let topic = Topic::generate();
+    let first = upsert_project(
+        project_id.clone(),
+        &app_domain,
+        topic.clone(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(first.inserted);
+
+    // Callers generate fresh keys on every request, these must not replace the stored ones
+    let second = upsert_project(
+        project_id.clone(),
+        &app_domain,
+        topic,
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(!second.inserted);
+    assert_eq!(second.keys.id, first.keys.id);
+    assert_eq!(
+        second.keys.authentication_public_key,
+        first.keys.authentication_public_key
+    );
+    assert_eq!(
+        second.keys.subscribe_public_key,
+        first.keys.subscribe_public_key
+    );
+    assert_eq!(second.keys.topic, first.keys.topic);
+    assert_eq!(second.keys.created_at, first.keys.created_at);
+    assert!(second.keys.updated_at > first.keys.updated_at);
+
+    let project = get_project_by_project_id(project_id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        project.authentication_public_key,
+        first.keys.authentication_public_key
+    );
+    assert_eq!(
+        project.subscribe_public_key,
+        first.keys.subscribe_public_key
+    );
+}
+
+#[tokio::test]
+async fn test_get_distinct_scopes_for_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    assert!(get_distinct_scopes_for_project(project.id, &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let scope1 = Uuid::new_v4();
+    let scope2 = Uuid::new_v4();
+    let scope3 = Uuid::new_v4();
+    let subscriber1 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber1.id,
+        HashSet::from([scope1, scope2]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let subscriber2 = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        subscriber2.id,
+        HashSet::from([scope2, scope3]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let other_project = helper_upsert_project(&postgres).await;
+    let other_subscriber =
+        helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+    update_subscriber_with_policy(
+        other_subscriber.id,
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_distinct_scopes_for_project(project.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashSet::from([scope1, scope2, scope3])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriber_by_id() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+
+    let found = get_subscriber_by_id(subscriber.id, &postgres, None)
+        .await
+        .unwrap();
+    let by_topic = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(found.id, subscriber.id);
+    assert_eq!(found.project, project.id);
+    assert_eq!(found.account, account);
+    assert_eq!(found.topic, subscriber.topic);
+    assert_eq!(found.sym_key, by_topic.sym_key);
+    assert_eq!(found.scope, by_topic.scope);
+
+    assert!(matches!(
+        get_subscriber_by_id(Uuid::new_v4(), &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_get_unsubscribed_accounts() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    assert!(get_unsubscribed_accounts(project.id, &[], &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let subscribed = generate_account_id();
+    helper_upsert_subscriber(project.id, subscribed.clone(), &postgres).await;
+    let expired = generate_account_id();
+    let expired_subscriber = helper_upsert_subscriber(project.id, expired.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired_subscriber.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    let unsubscribed = generate_account_id();
+    // Subscribed to a different project only
+    let other_project = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(other_project.id, unsubscribed.clone(), &postgres).await;
+
+    let result = get_unsubscribed_accounts(
+        project.id,
+        &[subscribed, expired.clone(), unsubscribed.clone()],
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        result.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([expired, unsubscribed])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscribers_for_project_in_paginated() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let mut accounts = Vec::new();
+    let mut subscriber_ids = HashSet::new();
+    for _ in 0..5 {
+        let account = generate_account_id();
+        let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+        accounts.push(account);
+        subscriber_ids.insert(subscriber.id);
+    }
+    // Not in the requested accounts
+    helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    let mut seen = Vec::new();
+    let mut after = None;
+    loop {
+        let (page, next) = get_subscribers_for_project_in_paginated(
+            project.id, &accounts, after, 2, &postgres, None,
+        )
+        .await
+        .unwrap();
+        if page.is_empty() {
+            assert!(next.is_none());
+            break;
+        }
+        assert!(page.len() <= 2);
+        seen.extend(page.into_iter().map(|s| s.id));
+        after = next;
+    }
+    assert_eq!(seen.len(), 5);
+    assert!(seen.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(seen.into_iter().collect::<HashSet<_>>(), subscriber_ids);
+
+    // Negative LIMIT is an error in Postgres, so it has to be clamped
+    let (page, next) =
+        get_subscribers_for_project_in_paginated(project.id, &accounts, None, -1, &postgres, None)
+            .await
+            .unwrap();
+    assert!(page.is_empty());
+    assert!(next.is_none());
+    let (page, _) = get_subscribers_for_project_in_paginated(
+        project.id,
+        &accounts,
+        None,
+        i64::MAX,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(page.len(), 5);
+}
+
+#[tokio::test]
+async fn test_get_projects_by_ids() {
+    let (postgres, _) = get_postgres().await;
+
+    assert!(get_projects_by_ids(&[], &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    helper_upsert_project(&postgres).await;
+
+    let projects =
+        get_projects_by_ids(&[project1.id, project2.id, Uuid::new_v4()], &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(
+        projects.into_iter().map(|p| p.id).collect::<HashSet<_>>(),
+        HashSet::from([project1.id, project2.id])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscription_watchers_for_account_and_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+
+    let project_did_key = generate_did_key();
+    let all_apps_did_key = generate_did_key();
+    for (project, did_key, expiry) in [
+        (Some(project.id), project_did_key.clone(), Duration::days(1)),
+        (None, all_apps_did_key.clone(), Duration::days(1)),
+        (
+            Some(other_project.id),
+            generate_did_key(),
+            Duration::days(1),
+        ),
+        (Some(project.id), generate_did_key(), Duration::days(-1)),
+    ] {
+        upsert_subscription_watcher(
+            account.clone(),
+            project,
+            &did_key,
+            &hex::encode(generate_subscribe_key().to_bytes()),
+            Utc::now() + expiry,
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    let watchers =
+        get_subscription_watchers_for_account_and_project(&account, project.id, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(
+        watchers
+            .into_iter()
+            .map(|w| w.did_key)
+            .collect::<HashSet<_>>(),
+        HashSet::from([project_did_key, all_apps_did_key])
+    );
+}
+
+#[tokio::test]
+async fn test_subscriber_created_at() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let before = Utc::now();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    let created_at = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .created_at;
+    assert!(created_at > before - Duration::seconds(5));
+    assert!(created_at < Utc::now() + Duration::seconds(5));
+
+    // Re-subscribing doesn't reset it
+    helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    assert_eq!(
+        get_subscriber_by_topic(subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .created_at,
+        created_at
+    );
+    let subscriptions =
+        get_subscriptions_by_account_and_maybe_app(account, None, true, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].created_at, created_at);
+}
+
+#[tokio::test]
+async fn test_extend_subscription_watcher_expiry() {
+    let (postgres, _) = get_postgres().await;
+
+    let did_key = generate_did_key();
+    assert!(
+        !extend_subscription_watcher_expiry(&did_key, Utc::now(), &postgres, None)
+            .await
+            .unwrap()
+    );
+
+    let sym_key = hex::encode(generate_subscribe_key().to_bytes());
+    upsert_subscription_watcher(
+        generate_account_id(),
+        None,
+        &did_key,
+        &sym_key,
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let new_expiry = Utc::now() + Duration::days(2);
+    assert!(
+        extend_subscription_watcher_expiry(&did_key, new_expiry, &postgres, None)
+            .await
+            .unwrap()
+    );
+    let watcher = get_subscription_watcher_by_did_key(&did_key, &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(watcher.sym_key, sym_key);
+    assert!((watcher.expiry - new_expiry).num_milliseconds().abs() < 1);
+
+    // Expired watchers aren't revived
+    upsert_subscription_watcher(
+        generate_account_id(),
+        None,
+        &did_key,
+        &sym_key,
+        Utc::now() - Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(
+        !extend_subscription_watcher_expiry(&did_key, new_expiry, &postgres, None)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_upsert_subscriber_topic_collision() {
+    let (postgres, _) = get_postgres().await;
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let scope = HashSet::from([Uuid::new_v4()]);
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let subscriber = upsert_subscriber_with_policy(
+        project1.id,
+        account.clone(),
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // Different key, but the same topic as the existing subscriber
+    let other_notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let result = upsert_subscriber_with_policy(
+        project2.id,
+        generate_account_id(),
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &other_notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriberError::TopicCollision(topic)) if topic == notify_topic
+    ));
+
+    // Re-upserting the same subscriber with its own topic is fine
+    let resubscribed = upsert_subscriber_with_policy(
+        project1.id,
+        account,
+        scope,
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(resubscribed.id, subscriber.id);
+    assert!(!resubscribed.inserted);
+}
+
+#[tokio::test]
+async fn test_get_total_counts() {
+    let (postgres, _) = get_postgres().await;
+
+    assert_eq!(get_total_project_count(&postgres, None).await.unwrap(), 0);
+    assert_eq!(
+        get_total_subscriber_count(&postgres, None).await.unwrap(),
+        0
+    );
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+    helper_upsert_subscriber(project2.id, generate_account_id(), &postgres).await;
+    let subscriber = helper_upsert_subscriber(project2.id, generate_account_id(), &postgres).await;
+
+    assert_eq!(get_total_project_count(&postgres, None).await.unwrap(), 2);
+    assert_eq!(
+        get_total_subscriber_count(&postgres, None).await.unwrap(),
+        3
+    );
+
+    soft_delete_subscriber(subscriber.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_total_subscriber_count(&postgres, None).await.unwrap(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscription_counts_by_app_for_account() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    assert!(
+        get_subscription_counts_by_app_for_account(account.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let project1 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    let project3 = helper_upsert_project(&postgres).await;
+    let expired = helper_upsert_subscriber(project3.id, account.clone(), &postgres).await;
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+
+    let mut counts = get_subscription_counts_by_app_for_account(account, &postgres, None)
+        .await
+        .unwrap();
+    counts.sort();
+    let mut expected = vec![(project1.app_domain, 1), (project2.app_domain, 1)];
+    expected.sort();
+    assert_eq!(counts, expected);
+}
+
+#[tokio::test]
+async fn test_get_subscribers_expiring_before() {
+    let (postgres, _) = get_postgres().await;
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let mut subscribers = vec![];
+    for (project, expiry) in [
+        (project1.id, Utc::now() + Duration::days(3)),
+        (project2.id, Utc::now() + Duration::days(1)),
+        (project1.id, Utc::now() + Duration::days(2)),
+        (project2.id, Utc::now() - Duration::days(1)),
+        (project1.id, Utc::now() + Duration::days(10)),
+    ] {
+        let subscriber = helper_upsert_subscriber(project, generate_account_id(), &postgres).await;
+        sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+            .bind(expiry)
+            .bind(subscriber.id)
+            .execute(&postgres)
+            .await
+            .unwrap();
+        subscribers.push(subscriber.id);
+    }
+
+    let threshold = Utc::now() + Duration::days(5);
+    let expiring = get_subscribers_expiring_before(threshold, 2, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        expiring.iter().map(|s| s.id).collect::<Vec<_>>(),
+        vec![subscribers[1], subscribers[2]]
+    );
+    assert_eq!(expiring[0].scope.len(), 2);
+
+    let expiring = get_subscribers_expiring_before(threshold, 10, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        expiring.iter().map(|s| s.id).collect::<Vec<_>>(),
+        vec![subscribers[1], subscribers[2], subscribers[0]]
+    );
+
+    assert!(
+        get_subscribers_expiring_before(threshold, -1, &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        get_subscribers_expiring_before(threshold, i64::MAX, &postgres, None)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriptions_by_account_and_project_id() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    let subscriber1 = helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+
+    let subscriptions = get_subscriptions_by_account_and_project_id(
+        account.clone(),
+        project1.project_id.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].app_domain, project1.app_domain);
+    assert_eq!(subscriptions[0].project_id, project1.project_id);
+    assert_eq!(subscriptions[0].account, account);
+    assert_eq!(subscriptions[0].scope.len(), 2);
+
+    assert!(get_subscriptions_by_account_and_project_id(
+        generate_account_id(),
+        project1.project_id.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .is_empty());
+
+    assert!(soft_delete_subscriber(subscriber1.id, &postgres, None)
+        .await
+        .unwrap());
+    assert!(get_subscriptions_by_account_and_project_id(
+        account.clone(),
+        project1.project_id,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .is_empty());
+
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    assert!(get_subscriptions_by_account_and_project_id(
+        account,
+        project2.project_id,
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+}
+
+#[tokio::test]
+async fn test_move_subscriber_to_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    let before = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+
+    move_subscriber_to_project(subscriber.id, project2.id, &postgres, None)
+        .await
+        .unwrap();
+    let after = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(after.id, subscriber.id);
+    assert_eq!(after.project, project2.id);
+    assert_eq!(after.scope, before.scope);
+    assert_eq!(after.expiry, before.expiry);
+    assert_eq!(after.sym_key, before.sym_key);
+
+    // The account already subscribes to project1 again, so it can't be moved back
+    helper_upsert_subscriber(project1.id, account, &postgres).await;
+    let result = move_subscriber_to_project(subscriber.id, project1.id, &postgres, None).await;
+    assert!(matches!(
+        result,
+        Err(MoveSubscriberToProjectError::SubscriberConflict(project)) if project == project1.id
+    ));
+    assert_eq!(
+        get_subscriber_by_topic(subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .project,
+        project2.id
+    );
+
+    assert!(matches!(
+        move_subscriber_to_project(Uuid::new_v4(), project1.id, &postgres, None).await,
+        Err(MoveSubscriberToProjectError::Sqlx(sqlx::Error::RowNotFound))
+    ));
+}
+
+#[tokio::test]
+async fn test_subscriber_updated_at() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let inserted = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(inserted.updated_at, inserted.created_at);
+
+    update_subscriber_with_policy(
+        subscriber.id,
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let updated = get_subscriber_by_topic(subscriber.topic, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(updated.created_at, inserted.created_at);
+    assert!(updated.updated_at > inserted.updated_at);
+}
+
+#[tokio::test]
+async fn test_project_notification_types() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let declared1 = Uuid::new_v4();
+    let declared2 = Uuid::new_v4();
+    let undeclared = Uuid::new_v4();
+
+    // Nothing declared, so anything goes
+    assert!(get_project_notification_types(project.id, &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+    validate_scopes_against_project(project.id, &HashSet::from([undeclared]), &postgres, None)
+        .await
+        .unwrap();
+
+    set_project_notification_types(
+        project.id,
+        HashSet::from([declared1, declared2]),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_project_notification_types(project.id, &postgres, None)
+            .await
+            .unwrap(),
+        HashSet::from([declared1, declared2])
+    );
+    validate_scopes_against_project(project.id, &HashSet::from([declared1]), &postgres, None)
+        .await
+        .unwrap();
+    let result = validate_scopes_against_project(
+        project.id,
+        &HashSet::from([declared1, undeclared]),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(ValidateScopeError::Scope(ScopeError::Undeclared(scopes))) if scopes == vec![undeclared]
+    ));
+
+    let account = generate_account_id();
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    let result = upsert_subscriber_with_policy(
+        project.id,
+        account.clone(),
+        HashSet::from([declared1, undeclared]),
+        ScopePolicy {
+            require_non_empty: true,
+            enforce_declared: true,
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriberError::Scope(ScopeError::Undeclared(scopes))) if scopes == vec![undeclared]
+    ));
+    assert!(
+        try_get_subscriber_by_topic(notify_topic.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let subscriber = upsert_subscriber_with_policy(
+        project.id,
+        account,
+        HashSet::from([declared1]),
+        ScopePolicy {
+            require_non_empty: true,
+            enforce_declared: true,
+        },
+        &notify_key,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    let result = update_subscriber_with_policy(
+        subscriber.id,
+        HashSet::from([undeclared]),
+        ScopePolicy {
+            require_non_empty: true,
+            enforce_declared: true,
+        },
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpdateSubscriberError::Scope(ScopeError::Undeclared(scopes))) if scopes == vec![undeclared]
+    ));
+    assert_eq!(
+        get_subscriber_by_topic(notify_topic.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .scope,
+        HashSet::from([declared1])
+    );
+
+    // Callers that don't enforce declared types can still store any scope
+    update_subscriber_with_policy(
+        subscriber.id,
+        HashSet::from([undeclared]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_subscriber_by_topic(notify_topic, &postgres, None)
+            .await
+            .unwrap()
+            .scope,
+        HashSet::from([undeclared])
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriber_delivery_targets() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account1 = generate_account_id();
+    let account2 = generate_account_id();
+    let deleted_account = generate_account_id();
+    let other_account = generate_account_id();
+    let subscriber1 = helper_upsert_subscriber(project.id, account1.clone(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, account2.clone(), &postgres).await;
+    let deleted = helper_upsert_subscriber(project.id, deleted_account.clone(), &postgres).await;
+    soft_delete_subscriber(deleted.id, &postgres, None)
+        .await
+        .unwrap();
+    helper_upsert_subscriber(other_project.id, other_account.clone(), &postgres).await;
+    helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    let mut expected = vec![];
+    for subscriber in [subscriber1, subscriber2] {
+        let sym_key = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .sym_key;
+        expected.push((subscriber.topic, sym_key));
+    }
+    expected.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+
+    let mut targets = get_subscriber_delivery_targets(
+        project.id,
+        &[account1, account2, deleted_account, other_account],
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    targets.sort_by(|a, b| a.0.as_ref().cmp(b.0.as_ref()));
+    assert_eq!(targets, expected);
+}
+
+#[tokio::test]
+async fn test_get_distinct_subscriber_account_count() {
+    let (postgres, _) = get_postgres().await;
+
+    assert_eq!(
+        get_distinct_subscriber_account_count(&postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    helper_upsert_subscriber(project2.id, account, &postgres).await;
+    helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+
+    assert_eq!(
+        get_total_subscriber_count(&postgres, None).await.unwrap(),
+        3
+    );
+    assert_eq!(
+        get_distinct_subscriber_account_count(&postgres, None)
+            .await
+            .unwrap(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_stream_subscribers_for_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+
+    let mut expected = HashSet::new();
+    for _ in 0..300 {
+        let subscriber =
+            helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+        expected.insert(subscriber.id);
+    }
+
+    let subscribers = stream_subscribers_for_project(project.id, &postgres, None)
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), expected.len());
+    assert_eq!(
+        subscribers.iter().map(|s| s.id).collect::<HashSet<_>>(),
+        expected
+    );
+    assert!(subscribers
+        .iter()
+        .all(|s| s.project == project.id && s.scope.len() == 2));
+}
+
+#[tokio::test]
+async fn test_upsert_subscription_watcher_sym_key_conflict() {
+    let (postgres, _) = get_postgres().await;
+
+    let account = generate_account_id();
+    let did_key = generate_did_key();
+    let sym_key = hex::encode(generate_subscribe_key().to_bytes());
+    upsert_subscription_watcher(
+        account.clone(),
+        None,
+        &did_key,
+        &sym_key,
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // Same did_key is an update, not a conflict
+    upsert_subscription_watcher(
+        account.clone(),
+        None,
+        &did_key,
+        &sym_key,
+        Utc::now() + Duration::days(2),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let other_did_key = generate_did_key();
+    let result = upsert_subscription_watcher(
+        account.clone(),
+        None,
+        &other_did_key,
+        &sym_key,
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(UpsertSubscriptionWatcherError::SymKeyConflict)
+    ));
+    assert!(
+        get_subscription_watcher_by_did_key(&other_did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Once the original watcher expired, the key can be reused
+    extend_subscription_watcher_expiry(&did_key, Utc::now() - Duration::days(1), &postgres, None)
+        .await
+        .unwrap();
+    upsert_subscription_watcher(
+        account,
+        None,
+        &other_did_key,
+        &sym_key,
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_upsert_subscription_watcher_concurrent_sym_key_conflict() {
+    let (postgres, _) = get_postgres().await;
+
+    let sym_key = hex::encode(generate_subscribe_key().to_bytes());
+    let results = futures::future::join_all((0..8).map(|_| {
+        let postgres = postgres.clone();
+        let sym_key = sym_key.clone();
+        async move {
+            upsert_subscription_watcher(
+                generate_account_id(),
+                None,
+                &generate_did_key(),
+                &sym_key,
+                Utc::now() + Duration::days(1),
+                &postgres,
+                None,
+            )
+            .await
+        }
+    }))
+    .await;
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert!(results.iter().all(|result| matches!(
+        result,
+        Ok(()) | Err(UpsertSubscriptionWatcherError::SymKeyConflict)
+    )));
+
+    let watchers = sqlx::query_scalar::<Postgres, i64>(
+        "SELECT COUNT(*) FROM subscription_watcher WHERE sym_key=$1",
+    )
+    .bind(&sym_key)
+    .fetch_one(&postgres)
+    .await
+    .unwrap();
+    assert_eq!(watchers, 1);
+}
+
+#[tokio::test]
+async fn test_update_subscriber_if_unchanged() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    let read = get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap();
+
+    let scope1 = HashSet::from([Uuid::new_v4()]);
+    let updated = update_subscriber_if_unchanged(
+        subscriber.id,
+        scope1.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        read.updated_at,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(updated.id, subscriber.id);
+    assert_eq!(
+        get_subscriber_by_topic(subscriber.topic.clone(), &postgres, None)
+            .await
+            .unwrap()
+            .scope,
+        scope1
+    );
+
+    // Lost the race, the subscriber was updated after it was read
+    let scope2 = HashSet::from([Uuid::new_v4()]);
+    assert!(update_subscriber_if_unchanged(
+        subscriber.id,
+        scope2,
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        read.updated_at,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .is_none());
+    assert_eq!(
+        get_subscriber_by_topic(subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .scope,
+        scope1
+    );
+
+    assert!(update_subscriber_if_unchanged(
+        Uuid::new_v4(),
+        HashSet::from([Uuid::new_v4()]),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        read.updated_at,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[tokio::test]
+async fn test_get_subscriber_accounts_with_expiry() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account1 = generate_account_id();
+    let account2 = generate_account_id();
+    let subscriber1 = helper_upsert_subscriber(project.id, account1.clone(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, account2.clone(), &postgres).await;
+    helper_upsert_subscriber(other_project.id, generate_account_id(), &postgres).await;
+
+    let expiry = Utc::now() - Duration::days(1);
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(expiry)
+        .bind(subscriber2.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let mut accounts = get_subscriber_accounts_with_expiry(project.project_id, &postgres, None)
+        .await
+        .unwrap();
+    accounts.sort_by_key(|(_, expiry)| *expiry);
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].0, account2);
+    assert!((accounts[0].1 - expiry).num_milliseconds().abs() < 1);
+    assert_eq!(accounts[1].0, account1);
+    assert_eq!(
+        accounts[1].1,
+        get_subscriber_by_topic(subscriber1.topic, &postgres, None)
+            .await
+            .unwrap()
+            .expiry
+    );
+}
+
+#[tokio::test]
+async fn test_renew_subscribers_for_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account1 = generate_account_id();
+    let account2 = generate_account_id();
+    let untouched_account = generate_account_id();
+    let subscriber1 = helper_upsert_subscriber(project.id, account1.clone(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, account2.clone(), &postgres).await;
+    let untouched = helper_upsert_subscriber(project.id, untouched_account, &postgres).await;
+    let other = helper_upsert_subscriber(other_project.id, account1.clone(), &postgres).await;
+    let scope_before = get_subscriber_by_topic(subscriber1.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .scope;
+    let untouched_expiry = get_subscriber_by_topic(untouched.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .expiry;
+    let other_expiry = get_subscriber_by_topic(other.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .expiry;
+
+    let before = Utc::now();
+    assert_eq!(
+        renew_subscribers_for_project(
+            project.id,
+            &[account1, account2, generate_account_id()],
+            Duration::days(60),
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap(),
+        2
+    );
+
+    for topic in [subscriber1.topic.clone(), subscriber2.topic] {
+        let expiry = get_subscriber_by_topic(topic, &postgres, None)
+            .await
+            .unwrap()
+            .expiry;
+        assert!(expiry > before + Duration::days(59));
+    }
+    assert_eq!(
+        get_subscriber_by_topic(subscriber1.topic, &postgres, None)
+            .await
+            .unwrap()
+            .scope,
+        scope_before
+    );
+    assert_eq!(
+        get_subscriber_by_topic(untouched.topic, &postgres, None)
+            .await
+            .unwrap()
+            .expiry,
+        untouched_expiry
+    );
+    assert_eq!(
+        get_subscriber_by_topic(other.topic, &postgres, None)
+            .await
+            .unwrap()
+            .expiry,
+        other_expiry
+    );
+}
+
+#[tokio::test]
+async fn test_get_project_keys_by_id() {
+    let (postgres, _) = get_postgres().await;
+
+    let authentication_key = generate_authentication_key();
+    let subscribe_key = generate_subscribe_key();
+    let project = upsert_project(
+        ProjectId::generate(),
+        &generate_app_domain(),
+        Topic::generate(),
+        &authentication_key,
+        &subscribe_key,
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap()
+    .keys;
+
+    let keys = get_project_keys_by_id(project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        keys.authentication_key.to_bytes(),
+        authentication_key.to_bytes()
+    );
+    assert_eq!(keys.subscribe_key.to_bytes(), subscribe_key.to_bytes());
+
+    assert!(matches!(
+        get_project_keys_by_id(Uuid::new_v4(), &postgres, None).await,
+        Err(GetProjectKeysError::Sqlx(sqlx::Error::RowNotFound))
+    ));
+
+    sqlx::query("UPDATE project SET authentication_private_key='not hex' WHERE id=$1")
+        .bind(project.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+    assert!(matches!(
+        get_project_keys_by_id(project.id, &postgres, None).await,
+        Err(GetProjectKeysError::DecodeKey(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_project_send_count() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let window_start = Utc::now().duration_trunc(Duration::minutes(1)).unwrap();
+    let next_window_start = window_start + Duration::minutes(1);
+
+    assert_eq!(
+        get_project_send_count(project.id, window_start, &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+
+    for expected in 1..=3 {
+        assert_eq!(
+            increment_project_send_count(project.id, window_start, &postgres, None)
+                .await
+                .unwrap(),
+            expected
+        );
+    }
+    assert_eq!(
+        get_project_send_count(project.id, window_start, &postgres, None)
+            .await
+            .unwrap(),
+        3
+    );
+
+    assert_eq!(
+        increment_project_send_count(project.id, next_window_start, &postgres, None)
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        get_project_send_count(project.id, window_start, &postgres, None)
+            .await
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        get_project_send_count(other_project.id, window_start, &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_get_subscriber_with_project_by_topic() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+
+    let result = get_subscriber_with_project_by_topic(subscriber.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(result.subscriber.id, subscriber.id);
+    assert_eq!(result.subscriber.project, project.id);
+    assert_eq!(result.subscriber.account, account);
+    assert_eq!(result.subscriber.topic, subscriber.topic);
+    assert_eq!(result.subscriber.scope.len(), 2);
+    assert_eq!(result.app_domain, project.app_domain);
+    assert_eq!(
+        result.authentication_public_key,
+        project.authentication_public_key
+    );
+
+    assert!(
+        get_subscriber_with_project_by_topic(Topic::generate(), &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn test_delete_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let subscriber = helper_upsert_subscriber(project.id, account.clone(), &postgres).await;
+    let other_subscriber =
+        helper_upsert_subscriber(other_project.id, account.clone(), &postgres).await;
+    let did_key = generate_did_key();
+    upsert_subscription_watcher(
+        account,
+        Some(project.id),
+        &did_key,
+        &hex::encode(generate_subscribe_key().to_bytes()),
+        Utc::now() + Duration::days(1),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+
+    delete_project(project.project_id.clone(), &postgres, None)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        get_project_by_id(project.id, &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+    assert!(
+        try_get_subscriber_by_topic(subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let scope_count = sqlx::query_scalar::<Postgres, i64>(
+        "SELECT COUNT(*) FROM subscriber_scope WHERE subscriber=$1",
+    )
+    .bind(subscriber.id)
+    .fetch_one(&postgres)
+    .await
+    .unwrap();
+    assert_eq!(scope_count, 0);
+    assert!(
+        get_subscription_watcher_by_did_key(&did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    get_project_by_id(other_project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert!(
+        try_get_subscriber_by_topic(other_subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    assert!(matches!(
+        delete_project(project.project_id, &postgres, None).await,
+        Err(DeleteProjectError::ProjectNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_get_subscriptions_by_accounts() {
+    let (postgres, _) = get_postgres().await;
+
+    let account1 = generate_account_id();
+    let account2 = generate_account_id();
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    helper_upsert_subscriber(project1.id, account1.clone(), &postgres).await;
+    helper_upsert_subscriber(project2.id, account1.clone(), &postgres).await;
+    helper_upsert_subscriber(project1.id, account2.clone(), &postgres).await;
+    let expired = helper_upsert_subscriber(project2.id, account2.clone(), &postgres).await;
+    helper_upsert_subscriber(project1.id, generate_account_id(), &postgres).await;
+    let project3 = helper_upsert_project(&postgres).await;
+    let deleted = helper_upsert_subscriber(project3.id, account1.clone(), &postgres).await;
+    assert!(soft_delete_subscriber(deleted.id, &postgres, None)
+        .await
+        .unwrap());
+
+    sqlx::query("UPDATE subscriber SET expiry=$1 WHERE id=$2")
+        .bind(Utc::now() - Duration::days(1))
+        .bind(expired.id)
+        .execute(&postgres)
+        .await
+        .unwrap();
+
+    let subscriptions =
+        get_subscriptions_by_accounts(&[account1.clone(), account2.clone()], &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(subscriptions.len(), 3);
+    let mut account1_app_domains = subscriptions
+        .iter()
+        .filter(|s| s.account == account1)
+        .map(|s| s.app_domain.clone())
+        .collect::<Vec<_>>();
+    account1_app_domains.sort();
+    let mut expected = vec![project1.app_domain.clone(), project2.app_domain];
+    expected.sort();
+    assert_eq!(account1_app_domains, expected);
+    let account2_subscriptions = subscriptions
+        .iter()
+        .filter(|s| s.account == account2)
+        .collect::<Vec<_>>();
+    assert_eq!(account2_subscriptions.len(), 1);
+    assert_eq!(account2_subscriptions[0].app_domain, project1.app_domain);
+    assert_eq!(account2_subscriptions[0].project_id, project1.project_id);
+
+    assert!(get_subscriptions_by_accounts(&[], &postgres, None)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_search_projects_by_app_domain_prefix() {
+    let (postgres, _) = get_postgres().await;
+
+    let prefix = format!("search{}", Uuid::new_v4().simple());
+    for app_domain in [
+        format!("{prefix}-a.example.com"),
+        format!("{prefix}-b.example.com"),
+        format!("{prefix}_c.example.com"),
+        format!("{prefix}xd.example.com"),
+    ] {
+        upsert_project(
+            ProjectId::generate(),
+            &app_domain,
+            Topic::generate(),
+            &generate_authentication_key(),
+            &generate_subscribe_key(),
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+    helper_upsert_project(&postgres).await;
+
+    let projects = search_projects_by_app_domain_prefix(&prefix, 10, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        projects
+            .iter()
+            .map(|p| p.app_domain.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            format!("{prefix}-a.example.com"),
+            format!("{prefix}-b.example.com"),
+            format!("{prefix}_c.example.com"),
+            format!("{prefix}xd.example.com"),
+        ]
+    );
+
+    let projects =
+        search_projects_by_app_domain_prefix(&prefix.to_ascii_uppercase(), 2, &postgres, None)
+            .await
+            .unwrap();
+    assert_eq!(projects.len(), 2);
+
+    // `_` is matched literally rather than as any single character
+    let projects = search_projects_by_app_domain_prefix(&format!("{prefix}_"), 10, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(projects.len(), 1);
+    assert_eq!(projects[0].app_domain, format!("{prefix}_c.example.com"));
+
+    assert!(
+        search_projects_by_app_domain_prefix(&format!("{prefix}%"), 10, &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn test_notification_types_for_project() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let labeled = Uuid::new_v4();
+    let unlabeled = Uuid::new_v4();
+
+    // Labeling an undeclared type doesn't declare it, which would start enforcing declared types
+    assert!(!upsert_notification_type(
+        project.id,
+        labeled,
+        "Promotions",
+        Some("Special offers"),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap());
+    assert!(
+        get_notification_types_for_project(project.id, &postgres, None)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    validate_scopes_against_project(project.id, &HashSet::from([unlabeled]), &postgres, None)
+        .await
+        .unwrap();
+
+    set_project_notification_types(
+        project.id,
+        HashSet::from([labeled, unlabeled]),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(upsert_notification_type(
+        project.id,
+        labeled,
+        "Promotions",
+        Some("Special offers"),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap());
+    assert!(
+        upsert_notification_type(project.id, labeled, "Offers", None, &postgres, None)
+            .await
+            .unwrap()
+    );
+    let types = get_notification_types_for_project(project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(types.len(), 2);
+    let labeled_type = types.iter().find(|t| t.scope_id == labeled).unwrap();
+    assert_eq!(labeled_type.name.as_deref(), Some("Offers"));
+    assert_eq!(labeled_type.description, None);
+    let unlabeled_type = types.iter().find(|t| t.scope_id == unlabeled).unwrap();
+    assert_eq!(unlabeled_type.name, None);
+
+    // Labels of types that remain declared are kept
+    set_project_notification_types(project.id, HashSet::from([labeled]), &postgres, None)
+        .await
+        .unwrap();
+    let types = get_notification_types_for_project(project.id, &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(types.len(), 1);
+    assert_eq!(types[0].scope_id, labeled);
+    assert_eq!(types[0].name.as_deref(), Some("Offers"));
+
+    assert!(get_notification_types_for_project(
+        helper_upsert_project(&postgres).await.id,
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+}
+
+#[tokio::test]
+async fn test_count_expired_subscription_watchers() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let active_did_key = generate_did_key();
+    for (did_key, expiry) in [
+        (active_did_key.clone(), Utc::now() + Duration::days(1)),
+        (generate_did_key(), Utc::now() - Duration::days(1)),
+        (generate_did_key(), Utc::now() - Duration::days(2)),
+    ] {
+        upsert_subscription_watcher(
+            account.clone(),
+            Some(project.id),
+            &did_key,
+            &hex::encode(generate_subscribe_key().to_bytes()),
+            expiry,
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(
+        count_expired_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        2
+    );
+    // Counting doesn't delete anything
+    assert_eq!(
+        count_expired_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        delete_expired_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        2
+    );
+    assert_eq!(
+        count_expired_subscription_watchers(&postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(
+        get_subscription_watcher_by_did_key(&active_did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[tokio::test]
+async fn test_statement_timeout() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+    assert_eq!(
+        get_subscriber_topics_with_timeout(std::time::Duration::from_secs(10), &postgres, None)
+            .await
+            .unwrap(),
+        vec![subscriber.topic]
+    );
+    assert_eq!(
+        get_project_topics_with_timeout(std::time::Duration::from_secs(10), &postgres, None)
+            .await
+            .unwrap(),
+        vec![project.topic]
+    );
+
+    let mut txn = begin_with_statement_timeout(std::time::Duration::from_millis(10), &postgres)
+        .await
+        .unwrap();
+    let result = sqlx::query("SELECT pg_sleep(1)")
+        .execute(&mut *txn)
+        .await
+        .map_err(ScanError::from);
+    assert!(matches!(result, Err(ScanError::StatementTimeout)));
+
+    // 0 would mean no timeout to Postgres
+    assert!(matches!(
+        begin_with_statement_timeout(std::time::Duration::ZERO, &postgres).await,
+        Err(ScanError::ZeroStatementTimeout)
+    ));
+    let mut txn = begin_with_statement_timeout(std::time::Duration::from_micros(1), &postgres)
+        .await
+        .unwrap();
+    let statement_timeout = sqlx::query_scalar::<Postgres, String>("SHOW statement_timeout")
+        .fetch_one(&mut *txn)
         .await
         .unwrap();
-    assert_eq!(accounts, vec![account_id.clone()]);
+    assert_eq!(statement_timeout, "1ms");
+    txn.rollback().await.unwrap();
 
-    let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
-            .await
-            .unwrap();
-    assert_eq!(subscribers.len(), 2);
-    for subscriber in subscribers {
-        if subscriber.app_domain == app_domain.as_ref() {
-            assert_eq!(subscriber.app_domain, app_domain.as_ref());
-            assert_eq!(subscriber.account, account_id);
-            assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key));
-            assert_eq!(subscriber.scope, subscriber_scope);
-            assert!(subscriber.expiry > Utc::now() + Duration::days(29));
-        } else {
-            assert_eq!(subscriber.app_domain, app_domain2.as_ref());
-            assert_eq!(subscriber.account, account_id);
-            assert_eq!(subscriber.sym_key, hex::encode(subscriber_sym_key2));
-            assert_eq!(subscriber.scope, subscriber_scope2);
-            assert!(subscriber.expiry > Utc::now() + Duration::days(29));
-        }
-    }
+    // Other errors aren't mistaken for timeouts
+    let mut txn = postgres.begin().await.unwrap();
+    let result = sqlx::query("SELECT * FROM no_such_table")
+        .execute(&mut *txn)
+        .await
+        .map_err(ScanError::from);
+    assert!(matches!(result, Err(ScanError::Sqlx(_))));
 }
 
 #[tokio::test]
-async fn test_get_subscriber_accounts_by_project_id() {
+async fn test_upsert_subscriber_idempotent() {
     let (postgres, _) = get_postgres().await;
 
-    let project_id = ProjectId::generate();
-    let app_domain = &generate_app_domain();
-    let topic = Topic::generate();
-    let subscribe_key = generate_subscribe_key();
-    let authentication_key = generate_authentication_key();
-    upsert_project(
-        project_id.clone(),
-        app_domain,
-        topic,
-        &authentication_key,
-        &subscribe_key,
-        &postgres,
-        None,
-    )
-    .await
-    .unwrap();
-    let project = get_project_by_project_id(project_id.clone(), &postgres, None)
-        .await
-        .unwrap();
-
+    let project = helper_upsert_project(&postgres).await;
     let account = generate_account_id();
     let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
     let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
     let notify_topic = topic_from_key(&notify_key);
-    upsert_subscriber(
+
+    let first = upsert_subscriber_with_policy(
         project.id,
         account.clone(),
-        scope,
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
         &notify_key,
-        notify_topic,
+        notify_topic.clone(),
+        &postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    assert!(first.inserted);
+    let second = upsert_subscriber_with_policy(
+        project.id,
+        account,
+        scope.clone(),
+        ScopePolicy {
+            require_non_empty: true,
+            ..Default::default()
+        },
+        &notify_key,
+        notify_topic.clone(),
         &postgres,
         None,
     )
     .await
     .unwrap();
+    assert!(!second.inserted);
+    assert_eq!(second.id, first.id);
+    assert_eq!(second.topic, first.topic);
 
-    let accounts = get_subscriber_accounts_by_project_id(project_id, &postgres, None)
+    let subscriber = get_subscriber_by_topic(notify_topic, &postgres, None)
         .await
         .unwrap();
-    assert_eq!(accounts, vec![account]);
+    assert_eq!(subscriber.scope, scope);
 }
 
 #[tokio::test]
-async fn test_get_subscriber_accounts_and_scopes_by_project_id() {
+async fn test_upsert_subscriber_concurrent_scope_replacement() {
     let (postgres, _) = get_postgres().await;
 
-    let project_id = ProjectId::generate();
-    let app_domain = &generate_app_domain();
-    let topic = Topic::generate();
-    let subscribe_key = generate_subscribe_key();
-    let authentication_key = generate_authentication_key();
-    upsert_project(
-        project_id.clone(),
-        app_domain,
-        topic,
-        &authentication_key,
-        &subscribe_key,
+    let project = helper_upsert_project(&postgres).await.id;
+    let account = generate_account_id();
+    let scopes = (0..8)
+        .map(|_| (0..5).map(|_| Uuid::new_v4()).collect::<HashSet<_>>())
+        .collect::<Vec<_>>();
+
+    let results = futures::future::join_all(scopes.iter().map(|scope| {
+        let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+        let postgres = postgres.clone();
+        let account = account.clone();
+        let scope = scope.clone();
+        async move {
+            upsert_subscriber_with_policy(
+                project,
+                account,
+                scope,
+                ScopePolicy {
+                    require_non_empty: true,
+                    ..Default::default()
+                },
+                &notify_key,
+                topic_from_key(&notify_key),
+                &postgres,
+                None,
+            )
+            .await
+        }
+    }))
+    .await;
+    let results = results.into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(results.iter().filter(|result| result.inserted).count(), 1);
+    assert!(results.iter().all(|result| result.id == results[0].id));
+
+    // Whichever upsert committed last, its scope is there in full and nothing from the others
+    let subscribers = get_subscribers_for_project_in(project, &[account], &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert!(scopes.contains(&subscribers[0].scope));
+}
+
+#[tokio::test]
+async fn test_try_get_project_by_topic() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let found = try_get_project_by_topic(project.topic.clone(), &postgres, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, project.id);
+
+    assert!(try_get_project_by_topic(Topic::generate(), &postgres, None)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        get_project_by_topic(Topic::generate(), &postgres, None).await,
+        Err(sqlx::Error::RowNotFound)
+    ));
+}
+
+#[tokio::test]
+async fn test_get_notify_targets_for_project_in() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let other_project = helper_upsert_project(&postgres).await;
+    let account1 = generate_account_id();
+    let account2 = generate_account_id();
+    let deleted_account = generate_account_id();
+    let other_account = generate_account_id();
+    let subscriber1 = helper_upsert_subscriber(project.id, account1.clone(), &postgres).await;
+    let subscriber2 = helper_upsert_subscriber(project.id, account2.clone(), &postgres).await;
+    let deleted = helper_upsert_subscriber(project.id, deleted_account.clone(), &postgres).await;
+    soft_delete_subscriber(deleted.id, &postgres, None)
+        .await
+        .unwrap();
+    helper_upsert_subscriber(other_project.id, other_account.clone(), &postgres).await;
+    helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    let targets = get_notify_targets_for_project_in(
+        project.id,
+        &[
+            account1.clone(),
+            account2.clone(),
+            deleted_account,
+            other_account,
+        ],
         &postgres,
         None,
     )
     .await
     .unwrap();
-    let project = get_project_by_project_id(project_id.clone(), &postgres, None)
+    assert_eq!(targets.len(), 2);
+    for (account, subscriber) in [(account1, subscriber1), (account2, subscriber2)] {
+        let target = targets.iter().find(|t| t.account == account).unwrap();
+        assert_eq!(target.topic, subscriber.topic);
+        let sym_key = get_subscriber_by_topic(subscriber.topic, &postgres, None)
+            .await
+            .unwrap()
+            .sym_key;
+        assert_eq!(target.sym_key, sym_key);
+    }
+}
+
+#[tokio::test]
+async fn test_delete_subscription_watchers_for_account() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let account = generate_account_id();
+    let other_account = generate_account_id();
+    let other_did_key = generate_did_key();
+    for (account, did_key, project, expiry) in [
+        (
+            account.clone(),
+            generate_did_key(),
+            Some(project.id),
+            Utc::now() + Duration::days(1),
+        ),
+        (
+            account.clone(),
+            generate_did_key(),
+            None,
+            Utc::now() + Duration::days(1),
+        ),
+        (
+            account.clone(),
+            generate_did_key(),
+            Some(project.id),
+            Utc::now() - Duration::days(1),
+        ),
+        (
+            other_account.clone(),
+            other_did_key.clone(),
+            Some(project.id),
+            Utc::now() + Duration::days(1),
+        ),
+    ] {
+        upsert_subscription_watcher(
+            account,
+            project,
+            &did_key,
+            &hex::encode(generate_subscribe_key().to_bytes()),
+            expiry,
+            &postgres,
+            None,
+        )
+        .await
+        .unwrap();
+    }
+
+    assert_eq!(
+        delete_subscription_watchers_for_account(account.clone(), &postgres, None)
+            .await
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        count_watchers_for_account(&account, &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(
+        get_subscription_watcher_by_did_key(&other_did_key, &postgres, None)
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(
+        delete_subscription_watchers_for_account(account, &postgres, None)
+            .await
+            .unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_subscriber_preferences() {
+    let (postgres, _) = get_postgres().await;
+
+    let project = helper_upsert_project(&postgres).await;
+    let subscriber = helper_upsert_subscriber(project.id, generate_account_id(), &postgres).await;
+
+    // Existing subscribers default to no restrictions
+    assert_eq!(
+        get_subscriber_preferences(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        SubscriberPreferences::default()
+    );
+
+    let preferences = SubscriberPreferences {
+        quiet_hours_start: Some(NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+        quiet_hours_end: Some(NaiveTime::from_hms_opt(7, 30, 0).unwrap()),
+        max_per_day: Some(5),
+    };
+    upsert_subscriber_preferences(subscriber.id, preferences.clone(), &postgres, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_subscriber_preferences(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        preferences
+    );
+
+    let preferences = SubscriberPreferences {
+        quiet_hours_start: None,
+        quiet_hours_end: None,
+        max_per_day: Some(10),
+    };
+    upsert_subscriber_preferences(subscriber.id, preferences.clone(), &postgres, None)
         .await
         .unwrap();
+    assert_eq!(
+        get_subscriber_preferences(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        preferences
+    );
+
+    assert!(matches!(
+        upsert_subscriber_preferences(
+            subscriber.id,
+            SubscriberPreferences {
+                quiet_hours_start: Some(NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+                quiet_hours_end: None,
+                max_per_day: None,
+            },
+            &postgres,
+            None,
+        )
+        .await,
+        Err(UpsertSubscriberPreferencesError::IncompleteQuietHours)
+    ));
+    assert!(matches!(
+        upsert_subscriber_preferences(
+            subscriber.id,
+            SubscriberPreferences {
+                quiet_hours_start: None,
+                quiet_hours_end: None,
+                max_per_day: Some(-1),
+            },
+            &postgres,
+            None,
+        )
+        .await,
+        Err(UpsertSubscriberPreferencesError::NegativeMaxPerDay)
+    ));
+    assert_eq!(
+        get_subscriber_preferences(subscriber.id, &postgres, None)
+            .await
+            .unwrap(),
+        preferences
+    );
+}
+
+#[tokio::test]
+async fn test_delete_subscribers_for_account() {
+    let (postgres, _) = get_postgres().await;
 
+    let project1 = helper_upsert_project(&postgres).await;
+    let project2 = helper_upsert_project(&postgres).await;
+    let project3 = helper_upsert_project(&postgres).await;
     let account = generate_account_id();
-    let scope = HashSet::from([Uuid::new_v4(), Uuid::new_v4()]);
-    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
-    let notify_topic = topic_from_key(&notify_key);
-    upsert_subscriber(
-        project.id,
+    let other_account = generate_account_id();
+    let subscriber1 = helper_upsert_subscriber(project1.id, account.clone(), &postgres).await;
+    helper_upsert_subscriber(project2.id, account.clone(), &postgres).await;
+    let soft_deleted = helper_upsert_subscriber(project3.id, account.clone(), &postgres).await;
+    soft_delete_subscriber(soft_deleted.id, &postgres, None)
+        .await
+        .unwrap();
+    let other = helper_upsert_subscriber(project1.id, other_account, &postgres).await;
+
+    assert_eq!(
+        delete_subscribers_for_account(account.clone(), &postgres, None)
+            .await
+            .unwrap(),
+        3
+    );
+    assert!(get_subscriptions_by_account_and_maybe_app(
         account.clone(),
-        scope.clone(),
-        &notify_key,
-        notify_topic,
-        &postgres,
         None,
+        true,
+        &postgres,
+        None
+    )
+    .await
+    .unwrap()
+    .is_empty());
+    let scope_count = sqlx::query_scalar::<Postgres, i64>(
+        "SELECT COUNT(*) FROM subscriber_scope WHERE subscriber=$1",
     )
+    .bind(subscriber1.id)
+    .fetch_one(&postgres)
     .await
     .unwrap();
+    assert_eq!(scope_count, 0);
+    assert!(try_get_subscriber_by_topic(other.topic, &postgres, None)
+        .await
+        .unwrap()
+        .is_some());
 
-    let subscribers =
-        get_subscribers_by_project_id_and_accounts(project_id, &[account.clone()], &postgres, None)
-            .await
-            .unwrap();
     assert_eq!(
-        subscribers,
-        vec![SubscriberAccountAndScopes { account, scope }]
+        delete_subscribers_for_account(account, &postgres, None)
+            .await
+            .unwrap(),
+        0
     );
 }
 
@@ -11293,14 +15713,14 @@ async fn get_subscriptions_by_account_and_maybe_app_unread_count_multiple_scopes
     .unwrap();
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 0);
 
@@ -11327,6 +15747,7 @@ async fn get_subscriptions_by_account_and_maybe_app_unread_count_multiple_scopes
     let subscribers = get_subscriptions_by_account_and_maybe_app(
         account_id.clone(),
         Some(&app_domain),
+        true,
         &postgres,
         None,
     )
@@ -11336,7 +15757,7 @@ async fn get_subscriptions_by_account_and_maybe_app_unread_count_multiple_scopes
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 1);
 }
@@ -11382,14 +15803,14 @@ async fn get_subscriptions_by_account_and_maybe_app_unread() {
     .unwrap();
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 0);
 
@@ -11430,6 +15851,7 @@ async fn get_subscriptions_by_account_and_maybe_app_unread() {
     let subscribers = get_subscriptions_by_account_and_maybe_app(
         account_id.clone(),
         Some(&app_domain),
+        true,
         &postgres,
         None,
     )
@@ -11439,19 +15861,19 @@ async fn get_subscriptions_by_account_and_maybe_app_unread() {
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 1);
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 1);
 
@@ -11471,14 +15893,14 @@ async fn get_subscriptions_by_account_and_maybe_app_unread() {
     );
 
     let subscribers =
-        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, &postgres, None)
+        get_subscriptions_by_account_and_maybe_app(account_id.clone(), None, true, &postgres, None)
             .await
             .unwrap();
     assert_eq!(subscribers.len(), 1);
     let sub = &subscribers[0];
     assert_eq!(sub.app_domain, project.app_domain);
     assert_eq!(sub.account, account_id);
-    assert_eq!(sub.sym_key, hex::encode(subscriber_sym_key));
+    assert_eq!(sub.sym_key, SymKey::from(subscriber_sym_key));
     assert_eq!(sub.scope, subscriber_scope);
     assert_eq!(sub.unread_notification_count, 0);
 }
@@ -11634,6 +16056,44 @@ async fn e2e_mark_notification_as_read(notify_server: &NotifyServerContext) {
     assert_eq!(subs[0].unread_notification_count, 0);
 }
 
+async fn helper_upsert_project(postgres: &PgPool) -> Project {
+    let project_id = ProjectId::generate();
+    upsert_project(
+        project_id.clone(),
+        &generate_app_domain(),
+        Topic::generate(),
+        &generate_authentication_key(),
+        &generate_subscribe_key(),
+        postgres,
+        None,
+    )
+    .await
+    .unwrap();
+    get_project_by_project_id(project_id, postgres, None)
+        .await
+        .unwrap()
+}
+
+async fn helper_upsert_subscriber(
+    project: Uuid,
+    account: AccountId,
+    postgres: &PgPool,
+) -> SubscribeResponse {
+    let notify_key = rand::Rng::gen::<[u8; 32]>(&mut rand::thread_rng());
+    let notify_topic = topic_from_key(&notify_key);
+    upsert_subscriber(
+        project,
+        account,
+        HashSet::from([Uuid::new_v4(), Uuid::new_v4()]),
+        &notify_key,
+        notify_topic,
+        postgres,
+        None,
+    )
+    .await
+    .unwrap()
+}
+
 async fn helper_get_notifications() -> (Uuid, Uuid, PgPool) {
     let (postgres, _) = get_postgres().await;
     let results = helper_get_notifications_with_postgres(&postgres).await;